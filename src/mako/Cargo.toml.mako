@@ -1,4 +1,4 @@
-<%! from util import (estr, enclose_in, hash_comment, library_to_crate_name, to_extern_crate_name) %>\
+<%! from util import (estr, enclose_in, hash_comment, library_to_crate_name, to_extern_crate_name, MOCKABLE_FEATURE) %>\
 <%namespace name="util" file="lib/util.mako"/>\
 <%block filter="hash_comment">\
 <%util:gen_info source="${self.uri}" />\
@@ -28,17 +28,37 @@ path = "src/main.rs"
 [dependencies]
 hyper = "^ 0.14"
 hyper-rustls = "^0.22"
+## Must match the ones hyper-rustls uses, to configure client certificates of its connector
+rustls = "^0.19"
+rustls-native-certs = "^0.5"
 ## Must match the one hyper uses, otherwise there are duplicate similarly named `Mime` structs
 mime = "^ 0.2.0"
-serde = "^ 1.0"
+serde = "^ 1.0.181"
 serde_json = "^ 1.0"
-serde_derive = "^ 1.0"
+serde_derive = "^ 1.0.181"
 yup-oauth2 = "^ 5.0"
-tokio = "^ 1.0"
+## fs is used to read credentials files, and to upload files in the examples and the CLI
+tokio = { version = "^ 1.0", features = ["fs", "io-util", "sync", "time"] }
 itertools = "^ 0.10"
+futures = "^ 0.3"
+httpdate = "^ 1.0"
+rand = "^ 0.8"
+chrono = { version = "^ 0.4", features = ["serde"] }
+base64 = "^ 0.13"
+flate2 = "^ 1.0"
+tracing = "^ 0.1"
+% if not cargo.get('is_executable', False):
+async-trait = { version = "^ 0.1", optional = true }
+% endif
 % for dep in cargo.get('dependencies', list()):
 ${dep}
 % endfor
+% if not cargo.get('is_executable', False):
+
+[features]
+## Generates a trait per resource, implemented by the hub, to allow mocking its methods
+${MOCKABLE_FEATURE} = ["async-trait"]
+% endif
 
 <%
   api_name = util.library_name()
@@ -7,24 +7,29 @@
     from util import (new_context, rust_comment, rust_doc_comment, rust_module_doc_comment,
                       rb_type, hub_type, mangle_ident, hub_type_params_s, hub_type_bounds,
                       rb_type_params_s, find_fattest_resource, HUB_TYPE_PARAMETERS, METHODS_RESOURCE,
-                      UNUSED_TYPE_MARKER, schema_markers)
+                      UNUSED_TYPE_MARKER, schema_markers, mockable_methods, operation_pollers,
+                      operation_result_schemas, operation_json_values)
 
     c = new_context(schemas, resources, context.get('methods'))
     hub_type = hub_type(c.schemas, util.canonical_name())
+    # types operation results decode into aren't used by any method directly, but are needed nonetheless
+    operation_results = operation_result_schemas(c, api.get('operation_results'))
+    json_values = operation_json_values(c)
     ht_params = hub_type_params_s()
 
     default_user_agent = "google-api-rust-client/" + cargo.build_version
+    default_api_client = "gdcl/" + cargo.build_version
+    default_endpoint = 'client::Endpoint::new("%s", "%s", %s)' % (
+        rootUrl, servicePath, context.get('mtlsRootUrl') and 'Some("%s")' % mtlsRootUrl or 'None')
 %>\
 use std::collections::HashMap;
-use std::cell::RefCell;
-use std::borrow::BorrowMut;
 use std::default::Default;
 use std::collections::BTreeMap;
 use serde_json as json;
 use std::io;
 use std::fs;
 use std::mem;
-use std::thread::sleep;
+use std::sync::Arc;
 
 use crate::client;
 
@@ -48,12 +53,21 @@ ${lib.scope_enum()}
 <%block filter="rust_doc_comment">\
 ${lib.hub_usage_example(c)}\
 </%block>
+///
+/// The hub is cheap to clone, and can be shared between tasks. All clones use the same connection pool
+/// and authenticator.
+#[derive(Clone)]
 pub struct ${hub_type}${ht_params} {
-    client: RefCell<C>,
-    auth: RefCell<oauth2::authenticator::Authenticator<hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>>>,
+    client: S,
+    auth: Arc<dyn client::GetToken>,
     _user_agent: String,
+    _headers: hyper::HeaderMap,
+    _endpoint: client::Endpoint,
     _base_url: String,
     _root_url: String,
+    _timeout: Option<std::time::Duration>,
+    _upload_chunk_timeout: Option<std::time::Duration>,
+    _compression: client::Compression,
 }
 
 impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> client::Hub for ${hub_type}${ht_params} {}
@@ -61,13 +75,29 @@ impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> client::Hub for ${hub_type}${ht_para
 impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> ${hub_type}${ht_params}
     where  ${', '.join(hub_type_bounds())} {
 
-    pub fn new(client: C, authenticator: oauth2::authenticator::Authenticator<hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>>) -> ${hub_type}${ht_params} {
+    /// Create a new hub which makes requests using the given client, and authorizes them using `auth`.
+    ///
+    /// The client is usually a `hyper::Client`, which may use any connector, e.g. one going through a proxy, or a
+    /// plain HTTP one to talk to a local mock server. It can also be any `tower` service wrapping one, see
+    /// `client::Transport`. `auth` can be `client::auth::default_credentials()`, a `yup-oauth2` authenticator, or anything
+    /// else implementing `client::GetToken`.
+    pub fn new<A: 'static + client::GetToken>(client: S, auth: A) -> ${hub_type}${ht_params} {
+        let endpoint = ${default_endpoint};
         ${hub_type} {
-            client: RefCell::new(client),
-            auth: RefCell::new(authenticator),
+            client,
+            auth: Arc::new(auth),
             _user_agent: "${default_user_agent}".to_string(),
-            _base_url: "${baseUrl}".to_string(),
-            _root_url: "${rootUrl}".to_string(),
+            _headers: {
+                let mut headers = hyper::HeaderMap::new();
+                client::replace_header(&mut headers, client::API_CLIENT_HEADER, Some(hyper::header::HeaderValue::from_static("${default_api_client}")));
+                headers
+            },
+            _base_url: endpoint.base_url(),
+            _root_url: endpoint.root_url(),
+            _endpoint: endpoint,
+            _timeout: None,
+            _upload_chunk_timeout: None,
+            _compression: Default::default(),
         }
     }
 
@@ -77,6 +107,20 @@ impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> ${hub_type}${ht_params}
     }
     % endfor
 
+    % if context.get('batchPath'):
+    /// Create a batch request, which sends multiple calls to the server at once.
+    ///
+    /// Calls are added using `add()`, and sent with `doit()`. The result of each call is then taken
+    /// from the returned responses using the handle `add()` returned for it.
+    pub fn batch(&self) -> client::BatchBuilder<'_, S> {
+        let batch = client::BatchBuilder::new(&self.client, &*self.auth, &self._user_agent, self._headers.clone(), self._root_url.clone() + "${batchPath}");
+        match self._timeout {
+            Some(timeout) => batch.timeout(timeout),
+            None => batch,
+        }
+    }
+
+    % endif
     /// Set the user-agent header field to use in all requests to the server.
     /// It defaults to `${default_user_agent}`.
     ///
@@ -85,7 +129,62 @@ impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> ${hub_type}${ht_params}
         mem::replace(&mut self._user_agent, agent_name)
     }
 
-    /// Set the base url to use in all requests to the server.
+    /// Set the project to bill for the quota of all requests, instead of the project of the credentials,
+    /// using the `x-goog-user-project` header. This is required for most APIs when using the credentials of a user.
+    /// Calls may override it using their `quota_project()` method.
+    ///
+    /// Returns the previously set quota project.
+    pub fn quota_project(&mut self, project: Option<hyper::header::HeaderValue>) -> Option<hyper::header::HeaderValue> {
+        client::replace_header(&mut self._headers, client::QUOTA_PROJECT_HEADER, project)
+    }
+
+    /// Set the reason for all requests, which is recorded in the audit logs of the resources, using the
+    /// `x-goog-request-reason` header. Calls may override it using their `request_reason()` method.
+    ///
+    /// Returns the previously set reason.
+    pub fn request_reason(&mut self, reason: Option<hyper::header::HeaderValue>) -> Option<hyper::header::HeaderValue> {
+        client::replace_header(&mut self._headers, client::REQUEST_REASON_HEADER, reason)
+    }
+
+    /// Set the value of the `x-goog-api-client` header, which identifies the library to the server.
+    /// It defaults to `${default_api_client}`.
+    ///
+    /// Returns the previously set value.
+    pub fn api_client(&mut self, api_client: Option<hyper::header::HeaderValue>) -> Option<hyper::header::HeaderValue> {
+        client::replace_header(&mut self._headers, client::API_CLIENT_HEADER, api_client)
+    }
+
+    /// Set where all requests are sent to, e.g. to use a regional endpoint or another universe domain.
+    /// It replaces the base url and the root url, which uploads and batch requests are sent to.
+    ///
+    /// ```ignore
+    /// hub.endpoint(hub.default_endpoint().region("europe-west1"))?;
+    /// ```
+    ///
+    /// Endpoints using mutual TLS fail with `Error::InvalidEndpoint`, as the client of the hub might not present
+    /// the client certificate. Use `https_endpoint()` to set them.
+    ///
+    /// Returns the previously set endpoint.
+    pub fn endpoint(&mut self, new_endpoint: client::Endpoint) -> client::Result<client::Endpoint> {
+        if new_endpoint.is_mtls() {
+            return Err(client::Error::InvalidEndpoint(
+                "mutual TLS requires a client presenting the certificate, see https_endpoint()".to_string()));
+        }
+        Ok(self.set_endpoint(new_endpoint))
+    }
+
+    fn set_endpoint(&mut self, new_endpoint: client::Endpoint) -> client::Endpoint {
+        self._base_url = new_endpoint.base_url();
+        self._root_url = new_endpoint.root_url();
+        mem::replace(&mut self._endpoint, new_endpoint)
+    }
+
+    /// The endpoint described by the discovery document of the API, to be configured and passed to `endpoint()`.
+    pub fn default_endpoint(&self) -> client::Endpoint {
+        ${default_endpoint}
+    }
+
+    /// Set the base url to use in all requests to the server, overriding the one of the endpoint.
     /// It defaults to `${baseUrl}`.
     ///
     /// Returns the previously set base url.
@@ -93,13 +192,55 @@ impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> ${hub_type}${ht_params}
         mem::replace(&mut self._base_url, new_base_url)
     }
 
-    /// Set the root url to use in all requests to the server.
+    /// Set the root url to use in all requests to the server, overriding the one of the endpoint.
     /// It defaults to `${rootUrl}`.
     ///
     /// Returns the previously set root url.
     pub fn root_url(&mut self, new_root_url: String) -> String {
         mem::replace(&mut self._root_url, new_root_url)
     }
+
+    /// Set the time after which an attempt to make a request is abandoned, unless the call sets its own timeout.
+    /// Whether the request is retried then is up to the delegate. It defaults to `None`, i.e. no timeout.
+    ///
+    /// Returns the previously set timeout.
+    pub fn timeout(&mut self, new_timeout: Option<std::time::Duration>) -> Option<std::time::Duration> {
+        mem::replace(&mut self._timeout, new_timeout)
+    }
+
+    /// Set the time after which an attempt to upload a single chunk of a resumable upload is abandoned.
+    /// It defaults to `None`, i.e. no timeout.
+    ///
+    /// Returns the previously set chunk timeout.
+    pub fn upload_chunk_timeout(&mut self, new_timeout: Option<std::time::Duration>) -> Option<std::time::Duration> {
+        mem::replace(&mut self._upload_chunk_timeout, new_timeout)
+    }
+
+    /// Set whether json responses, and large json request bodies, are compressed using gzip.
+    /// It defaults to no compression at all.
+    ///
+    /// Returns the previously set compression.
+    pub fn compression(&mut self, new_compression: client::Compression) -> client::Compression {
+        mem::replace(&mut self._compression, new_compression)
+    }
+}
+
+impl ${hub_type}<hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>> {
+    /// Set where all requests are sent to like `endpoint()`, and replace the client of the hub by one trusting the
+    /// root certificates of the system, which presents the client certificate if the endpoint uses mutual TLS.
+    ///
+    /// ```ignore
+    /// hub.https_endpoint(hub.default_endpoint().mtls(&cert_chain_pem, &private_key_pem))?;
+    /// ```
+    ///
+    /// Returns the previously set endpoint.
+    pub fn https_endpoint(&mut self, new_endpoint: client::Endpoint) -> client::Result<client::Endpoint> {
+        let connector = new_endpoint
+            .https_connector()
+            .map_err(|err| client::Error::InvalidEndpoint(err.to_string()))?;
+        self.client = hyper::Client::builder().build(connector);
+        Ok(self.set_endpoint(new_endpoint))
+    }
 }
 
 
@@ -108,11 +249,22 @@ impl<'a, ${', '.join(HUB_TYPE_PARAMETERS)}> ${hub_type}${ht_params}
 // SCHEMAS ###
 // ##########
 % for s in c.schemas.values():
-% if UNUSED_TYPE_MARKER not in schema_markers(s, c, transitive=True):
-${schema.new(s, c)}
+% if UNUSED_TYPE_MARKER not in schema_markers(s, c, transitive=True) or s.id in operation_results:
+${schema.new(s, c, json_values)}
 % endif
 % endfor
 % endif
+<% pollers = operation_pollers(c) %>\
+% if pollers:
+
+// ###############
+// OPERATIONS ###
+// #############
+
+% for op in pollers:
+${schema.operation(op, hub_type)}
+% endfor
+% endif
 
 // ###################
 // MethodBuilders ###
@@ -135,3 +287,14 @@ ${mbuild.new(resource, method, c)}
 
 % endfor ## method in methods
 % endfor ## resource, methods
+
+// ###################
+// Mockable Traits ###
+// #################
+
+% for resource in c.rta_map:
+% if mockable_methods(c, resource, api.get('no_upload_prefix')):
+${rbuild.mockable(resource, c)}
+
+% endif
+% endfor
@@ -7,7 +7,8 @@
                       REQUEST_MARKER_TRAIT, RESPONSE_MARKER_TRAIT, supports_scopes, to_api_version,
                       to_fqan, METHODS_RESOURCE, ADD_PARAM_MEDIA_EXAMPLE, PROTOCOL_TYPE_INFO, enclose_in,
                       upload_action_fn, METHODS_BUILDER_MARKER_TRAIT, DELEGATE_TYPE,
-                      to_extern_crate_name, rust_doc_sanitize)  
+                      to_extern_crate_name, rust_doc_sanitize, resource_trait_type, mockable_methods,
+                      operation_pollers)
 
     def pretty_name(name):
         return ' '.join(split_camelcase_s(name).split('.'))
@@ -29,6 +30,7 @@
     request_trait_url = REQUEST_MARKER_TRAIT
     response_trait_url = RESPONSE_MARKER_TRAIT
     part_trait_url = PART_MARKER_TRAIT
+    pollers = operation_pollers(c)
 
     doc_base_url = util.doc_base_url() + '/' + to_extern_crate_name(util.crate_name()) + '/'
 
@@ -56,6 +58,8 @@
                 array.append(m)
     # end for each method
     header_methods = (('Upload', upload_methods), ('Download', download_methods), ('Subscription', subscription_methods))
+    example_method_id = sorted(m.id for m in c.fqan_map.values())[0]
+    mockable_resources = [r for r in sorted(c.rta_map) if mockable_methods(c, r, api.get('no_upload_prefix'))]
 %>\
 This documentation was generated from *${util.canonical_name()}* crate version *${util.crate_version()}*, where *${revision is UNDEFINED and '00000000' or revision}* is the exact revision of the *${id}* schema built by the [mako](http://www.makotemplates.org/) code generator *v${cargo.build_version}*.
 % if documentationLink:
@@ -190,18 +194,111 @@ ${'##'} A complete example
 
 ${self.hub_usage_example(c, rust_doc, fr=fr)}\
 
+${'##'} Authentication
+
+The hub authorizes its requests using any implementation of ${link('GetToken', 'client::GetToken')}, which is passed
+to its constructor. The example above uses ${link('default_credentials()', 'client::auth::default_credentials')}, which finds
+*Application Default Credentials* like all Google client libraries do: it reads the key of a service account or the
+refresh token of a user from the file named by `GOOGLE_APPLICATION_CREDENTIALS`, or from the well-known file written by
+`gcloud auth application-default login`, and otherwise asks the metadata server of the environment. Use a
+${link('DefaultCredentialsBuilder', 'client::auth::DefaultCredentialsBuilder')} to configure these locations, or to act on
+behalf of a user using domain-wide delegation by setting a `subject`.
+
+Besides that, a `yup-oauth2` authenticator can be used, a `String` serves as static bearer token,
+${link('ApiKey', 'client::ApiKey')} sends an API key only, and ${link('NoToken', 'client::NoToken')} sends no credentials
+at all. Implement the trait yourself to obtain tokens from anywhere else.
+
+${'##'} Request Headers
+
+Besides `User-Agent` and `Authorization`, each request carries an `x-goog-api-client` header identifying this library,
+which can be changed using the hub's `api_client()` method. Use its `quota_project()` method to bill the quota of all
+requests to another project than the one of the credentials, as required by most APIs when using the credentials of
+a user, and `request_reason()` to record a reason in the audit logs. These headers are sent with every request,
+including each chunk of a resumable upload, and can be overridden per call using the methods of the same names of the
+${link('Method Builders', call_builder_url)}. All of these methods take a `hyper::header::HeaderValue`.
+
+${'##'} Endpoints
+
+Requests are sent to the endpoint described by the discovery document of the API. Pass an
+${link('Endpoint', 'client::Endpoint')} to the hub's `endpoint()` method to send them elsewhere, like to a regional
+endpoint or to another universe domain. The mTLS endpoint of the API requires the client of the hub to present a
+client certificate, which is why it has to be set using `https_endpoint()`, which also replaces the client by one
+presenting the certificate:
+
+```ignore
+hub.https_endpoint(hub.default_endpoint().mtls(&cert_chain_pem, &private_key_pem))?;
+```
+
+The URLs of uploads and batch requests are derived from the endpoint as well.
+
+${'##'} Compression
+
+To save bandwidth, e.g. for large lists, pass a ${link('Compression', 'client::Compression')} to the hub's `compression()`
+method. With `responses` set, the server is asked to compress json responses using gzip, which are then decompressed
+transparently. With `min_request_size` set, json request bodies of at least that size are sent gzip compressed, which
+only works with APIs accepting such requests.
+
+${'##'} Sharing the Hub
+
+The hub is `Clone + Send + Sync`, and the futures returned by the ${link('Method Builders', call_builder_url)} are `Send`.
+Clone the hub, or put it into an `Arc`, to use it from multiple tasks at once.
+All clones share one connection pool and one authenticator, and with it its token cache.
+
+${'##'} Middleware
+
+Instead of a `hyper::Client`, the hub accepts any [tower](https://docs.rs/tower) service which takes and returns
+hyper requests and responses, see ${link('Transport', 'client::Transport')}. This allows layering middleware for rate
+limits, concurrency limits, timeouts or metrics under the hub, like so:
+
+```ignore
+let service = tower::ServiceBuilder::new()
+    .buffer(64)
+    .rate_limit(100, std::time::Duration::from_secs(1))
+    .service(hyper::Client::builder().build(hyper_rustls::HttpsConnector::with_native_roots()));
+let hub = ${hub_url}::new(service, auth);
+```
+
+Delegates keep working on top of the service. Errors of the service are handed to `Delegate::http_error()`, and returned
+as `Error::HttpError`.
+
 ${'##'} Handling Errors
 
 All errors produced by the system are provided either as ${link('Result', 'client::Result')} enumeration as return value of
 the ${api.terms.action}() methods, or handed as possibly intermediate results to either the 
-${link('Hub Delegate', delegate_url)}, or the ${link('Authenticator Delegate', urls.authenticator_delegate)}.
+${link('Hub Delegate', delegate_url)}, or the ${link('Authenticator Delegate', urls.authenticator_delegate)}
+of the `yup-oauth2` authenticator.
 
 When delegates handle errors or intermediate values, they may have a chance to instruct the system to retry. This 
 makes the system potentially resilient to all kinds of errors.
+The ${link('RetryPolicy', 'client::RetryPolicy')} is a ready-made delegate which retries transient failures using
+exponential backoff, and which can also be used from within your own delegate.
+
+Errors reported by the server are returned as `Error::Api`, holding an ${link('ApiError', 'client::ApiError')}. Besides
+the status code and message, it provides the typed `details` of the error, like `ErrorInfo`, `RetryInfo` or the field
+violations of a bad request, as well as predicates like `is_not_found()` or `is_rate_limited()`:
+
+```ignore
+match result {
+    Err(Error::Api(err)) if err.is_not_found() => create_it(),
+    Err(Error::Api(err)) if err.is_rate_limited() => sleep(err.retry_delay().unwrap_or(default_delay)),
+    ...
+}
+```
+
+`Error::Api` replaces `Error::BadRequest`, which earlier versions returned for failed responses with a decodable body.
+Code matching `Error::BadRequest(response)` should match `Error::Api(err)` instead, where `err` is what used to be
+`response.error`.
+
+Requests don't time out by default. Set a timeout for all calls using the hub's `timeout()` method, or for a single call
+using its builder's `timeout()` method. Attempts taking longer are abandoned, and handed to `Delegate::timeout()`, which
+may decide to retry. Otherwise, the call fails with `Error::Timeout`. The chunks of resumable uploads are subject to a
+timeout of their own, set using the hub's `upload_chunk_timeout()` method.
 
 ${'##'} Uploads and Downloads
 If a method supports downloads, the response body, which is part of the ${link('Result', 'client::Result')}, should be
 read by you to obtain the media.
+The body is handed to you as-is, without being buffered into memory first, so large media can be streamed to its
+destination chunk by chunk.
 If such a method also supports a ${link('Response Result', 'client::ResponseResult')}, it will return that by default.
 You can see it as meta-data for the actual media. To trigger a media download, you will have to set up the builder by making
 this call: `${ADD_PARAM_MEDIA_EXAMPLE}`.
@@ -209,6 +306,69 @@ this call: `${ADD_PARAM_MEDIA_EXAMPLE}`.
 Methods supporting uploads can do so using up to ${len(PROTOCOL_TYPE_INFO)} different protocols: 
 ${put_and(md_italic(PROTOCOL_TYPE_INFO.keys()))}. The distinctiveness of each is represented by customized 
 `${api.terms.action}(...)` methods, which are then named ${put_and(enclose_in('`', ("%s(...)" % upload_action_fn(api.terms.upload_action, v['suffix']) for v in PROTOCOL_TYPE_INFO.values())))} respectively.
+The media is read from any ${link('ReadSeek', 'client::ReadSeek')} implementor, like `tokio::fs::File`, and streamed to the
+server chunk by chunk, without ever reading it into memory as a whole.
+
+Resumable uploads can be continued after they were interrupted, even by a restart of the process. While the upload
+makes progress, its ${link('UploadSession', 'client::UploadSession')} is handed to `Delegate::store_upload_session()`,
+along with the amount of confirmed bytes to `Delegate::upload_progress()`. The session is serializable, so it can be
+persisted and later passed to the `${upload_action_fn(api.terms.upload_action, PROTOCOL_TYPE_INFO['resumable']['suffix'])}_from_session(...)` method of the same call, which uploads the remaining bytes.
+
+${'##'} Pagination
+
+Methods which return their results in pages, i.e. which take a *page token* and return a *next page token*,
+additionally provide `pages()` and, if possible, `items()` on their ${link('Method Builder', call_builder_url)}.
+They return a [`Stream`](https://docs.rs/futures/0.3/futures/stream/trait.Stream.html) which requests one page
+after another as needed, yielding either the page itself or each item contained in it.
+
+% if pollers:
+${'##'} Long-running Operations
+
+Methods which take a while to complete return ${put_and([link('`%s`' % op.schema, 'api::' + op.schema) for op in pollers])},
+which has to be polled until it is done. Its `wait()` method does so using the hub, and fails with `Error::Operation`
+if the operation failed, whereas `poll_until_done()` returns the operation either way. The delay between polls, the
+time after which waiting fails with `Error::Timeout`, and a token to cancel the waiting early are configured using
+${link('PollOptions', 'client::PollOptions')}:
+
+```ignore
+let options = client::PollOptions::default().timeout(Some(Duration::from_secs(600)));
+let operation = operation.wait(&hub, &options).await?;
+```
+% if any(op.results for op in pollers):
+
+The `response_as()` and `metadata_as()` methods decode the result of a done operation into the type the method
+which started it documents. The ${link('Method Builders', call_builder_url)} of these methods also provide
+`${api.terms.action}_and_wait()`, which starts the operation, waits for it, and decodes its results into the types
+known for the method, or into a `serde_json::Value` otherwise.
+% endif
+
+% endif
+% if context.get('batchPath'):
+${'##'} Batch Requests
+
+Multiple calls can be sent to the server in a single request using the hub's `batch()` method. Add the
+${link('Method Builders', call_builder_url)} of the calls to the returned ${link('BatchBuilder', 'client::BatchBuilder')},
+send them using its `doit()` method, and take the result of each call from the returned responses.
+Calls which require an upload can't be part of a batch. The delegates of batched calls are only asked for API keys,
+as the calls are made by the batch request.
+
+% endif
+${'##'} Partial Responses
+
+Each resource provides a `fields()` function, returning a ${link('selector', 'client::Fields')} with a method for each
+of its fields. Pass it to the `fields()` method of a ${link('Method Builder', call_builder_url)} to make the server
+respond with the selected fields only, like `.fields(Foo::fields().id().name().bars_with(Bar::fields().id()))`,
+which sets the `fields` parameter to `id,name,bars(id)`.
+The same selector can produce the field mask expected by the `updateMask` parameter of methods patching a resource,
+using its `to_update_mask()` method.
+
+${'##'} Conditional Requests
+
+Each ${link('Method Builder', call_builder_url)} can add headers to its request using its `header()` method. For
+resources carrying an `etag`, `if_match()` makes sure not to overwrite changes made by someone else in the meantime,
+and `if_none_match()` allows to cheaply revalidate a cached copy: if it is still up to date, the call fails with
+`Error::NotModified` instead of transferring the resource again. Like `header()`, they take a `HeaderValue`, which
+is obtained from an `etag` using `HeaderValue::from_str(&etag)`, failing if it contains characters a header can't hold.
 
 ${'##'} Customization and Callbacks
 
@@ -219,6 +379,35 @@ retry on failure.
 
 The ${link('delegate trait', delegate_url)} is default-implemented, allowing you to customize it with minimal effort.
 
+${'##'} Tracing
+
+Each `${api.terms.action}()` call is made within a [tracing](https://docs.rs/tracing) span named `google_api_call`,
+carrying the method id, the HTTP method and the URL template. The number, status code, latency and bytes sent and received
+of the latest attempt are recorded to it as well, and each attempt and uploaded chunk emits an event.
+Headers and bodies are never traced, and the values of API keys and tokens in URLs are redacted.
+
+${'##'} Testing
+
+The ${link('testing', 'client::testing')} module allows to test code using the hub without network access or
+credentials. A `MockTransport` answers calls with canned responses, registered by method id and URL, and keeps the
+requests it received for inspection. A `Recorder` records the exchanges made through a real transport into a
+`Cassette` file, which a `Replayer` plays back deterministically. `FakeToken` stands in for the authenticator:
+
+```ignore
+use ${util.library_name()}::client::testing::{FakeToken, MockResponse, MockTransport};
+
+let mock = MockTransport::new();
+mock.respond("${example_method_id}", MockResponse::json(&expected));
+let hub = ${hub_url}::new(mock.clone(), FakeToken::default());
+```
+
+% if mockable_resources:
+With the `mockable` feature enabled, a trait is generated for each resource, like `${resource_trait_type(mockable_resources[0])}`.
+Its methods take their parameters as plain struct and return the value the server responded with. As the hub implements
+all of them, code using the traits instead of the hub can be tested with mocks, e.g. ones made by
+[mockall](https://docs.rs/mockall), or with in-memory fakes.
+% endif
+
 ${'##'} Optional Parts in Server-Requests
 
 All structures provided by this library are made to be ${link('encodable', request_trait_url)} and 
@@ -227,6 +416,10 @@ are valid.
 Most optionals are are considered ${link('Parts', part_trait_url)} which are identifiable by name, which will be sent to 
 the server to indicate either the set parts of the request or the desired parts in the response.
 
+Values which are transmitted as strings, but have a more natural representation, are decoded into it. 64 bit integers
+become `i64` or `u64`, base64 encoded bytes become `Vec<u8>`, and timestamps and durations become their respective
+[chrono](https://docs.rs/chrono) types, which are re-exported as `client::chrono`.
+
 ${'##'} Builder Arguments
 
 Using ${link('method builders', call_builder_url)}, you are able to prepare an action call by repeatedly calling it's methods.
@@ -234,6 +427,7 @@ These will always take a single argument, for which the following statements are
 
 * [PODs][wiki-pod] are handed by copy
 * strings are passed as `&str`
+* strings with a fixed set of possible values are passed as enums, which can also be created from a `&str`
 * ${link('request values', request_trait_url)} are moved
 
 Arguments will always be copied or cloned into the builder, to make them independent of their original life times.
@@ -249,26 +443,16 @@ Arguments will always be copied or cloned into the builder, to make them indepen
 ###############################################################################################
 <%def name="test_hub(hub_type, comments=True)">\
 use std::default::Default;
-use oauth2;
-use ${util.library_name()}::${hub_type};
+use ${util.library_name()}::{${hub_type}, client};
 
 % if comments:
-// Get an ApplicationSecret instance by some means. It contains the `client_id` and 
-// `client_secret`, among other things.
-% endif
-let secret: ApplicationSecret = Default::default();
-% if comments:
-// Instantiate the authenticator. It will choose a suitable authentication flow for you, 
-// unless you replace  `None` with the desired Flow.
-// Provide your own `AuthenticatorDelegate` to adjust the way it operates and get feedback about 
-// what's going on. You probably want to bring in your own `TokenStorage` to persist tokens and
-// retrieve them from storage.
+// Obtain credentials the way all Google client libraries do: from the file named by the
+// `GOOGLE_APPLICATION_CREDENTIALS` environment variable, the one written by
+// `gcloud auth application-default login`, or the metadata server of the environment.
+// Any other `client::GetToken` implementation, like a `yup-oauth2` authenticator, works as well.
 % endif
-let auth = yup_oauth2::InstalledFlowAuthenticator::builder(
-        secret,
-        yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
-    ).build().await.unwrap();
-let mut hub = ${hub_type}::new(hyper::Client::with_connector(hyper::net::HttpsConnector::new(hyper_rustls::TlsClient::new())), auth);\
+let auth = client::auth::default_credentials().await.unwrap();
+let mut hub = ${hub_type}::new(hyper::Client::builder().build(hyper_rustls::HttpsConnector::with_native_roots()), auth);\
 </%def>
 
 ## You will still have to set the filter for your comment type - either nothing, or rust_doc_comment !
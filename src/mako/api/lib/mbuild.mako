@@ -1,4 +1,5 @@
 <%!
+    from random import choice
     from util import (put_and, rust_test_fn_invisible, rust_doc_test_norun, rust_doc_comment,
                       rb_type, mb_type, singular, hub_type, to_fqan, indent_all_but_first_by,
                       activity_rust_type, mangle_ident, activity_input_type, get_word,
@@ -8,10 +9,12 @@
                       indent_by, to_rust_type, rnd_arg_val_for_type, extract_parts, mb_type_params_s,
                       hub_type_params_s, method_media_params, enclose_in, mb_type_bounds, method_response,
                       CALL_BUILDER_MARKERT_TRAIT, pass_through, markdown_rust_block, parts_from_params,
-                      DELEGATE_PROPERTY_NAME, struct_type_bounds_s, scope_url_to_variant,
+                      DELEGATE_PROPERTY_NAME, call_timeout_name, struct_type_bounds_s, scope_url_to_variant,
                       re_find_replacements, ADD_PARAM_FN, ADD_PARAM_MEDIA_EXAMPLE, upload_action_fn, METHODS_RESOURCE,
                       method_name_to_variant, size_to_bytes, method_default_scope,
-                      is_repeated_property, setter_fn_name, ADD_SCOPE_FN, rust_doc_sanitize, items)
+                      is_repeated_property, setter_fn_name, ADD_SCOPE_FN, FIELDS_FN, rust_doc_sanitize, items,
+                      is_paginated_method, pagination_item_property, PAGE_TOKEN_PARAM, NEXT_PAGE_TOKEN_PROPERTY,
+                      is_enum_property, enum_variants, operation_pollers, operation_result_types)
 
     def get_parts(part_prop):
         if not part_prop:
@@ -30,6 +33,7 @@
 %>\
 <%namespace name="util" file="../../lib/util.mako"/>\
 <%namespace name="lib" file="lib.mako"/>\
+<%namespace name="schema" file="schema.mako"/>\
 
 ## Creates a method builder type
 ###############################################################################################
@@ -56,6 +60,25 @@
     part_prop, parts = parts_from_params(params)
     part_desc = make_parts_desc(part_prop)
     parts = get_parts(part_prop)
+
+    # calls which can only be made with an upload can't be part of a batch
+    no_upload_variant = api.get('no_upload_prefix') is not None and ThisType.startswith(api.no_upload_prefix)
+    supports_batch = bool(context.get('batchPath')) and (not method_media_params(m) or no_upload_variant)
+
+    # the partial response can be selected in a typed manner, unless the method has a parameter of the same name
+    supports_fields = (response_schema and response_schema.get('properties') and 'fields' in parameters
+                       and FIELDS_FN not in [mangle_ident(setter_fn_name(p)) for p in params])
+    header_setters = [fn for fn in ('quota_project', 'request_reason')
+                      if fn not in [mangle_ident(setter_fn_name(p)) for p in params]]
+
+    # [(field, rust_type), ...] of the results of the long-running operation the method starts, as configured in
+    # api.operation_results, or untyped json. Methods getting an operation don't start it.
+    operation_results = list()
+    if response_schema and not method_media_params(m) and m.httpMethod != 'GET':
+        result_types = operation_result_types(c, m, api.get('operation_results'))
+        for op in operation_pollers(c):
+            if op.schema == response_schema.id:
+                operation_results = [(pn, result_types.get(pn, 'json::Value')) for pn in op.results]
 %>\
 % if 'description' in m:
 ${m.description | rust_doc_sanitize, rust_doc_comment}
@@ -64,7 +87,7 @@ ${m.description | rust_doc_sanitize, rust_doc_comment}
 % if m.get('supportsMediaDownload', False):
 /// This method supports **media download**. To enable it, adjust the builder like this:
 % if alt_param:
-/// `.${mangle_ident(setter_fn_name(alt_param))}("media")`.
+/// `.${mangle_ident(setter_fn_name(alt_param))}(${'enum_type' in alt_param and '"media".into()' or '"media"'})`.
 % else:
 /// `${ADD_PARAM_MEDIA_EXAMPLE}`.
 % endif
@@ -124,6 +147,7 @@ pub struct ${ThisType}
 % endfor
 ## A generic map for additinal parameters. Sometimes you can set some that are documented online only
     ${api.properties.params}: HashMap<String, String>,
+    ${api.properties.headers}: hyper::HeaderMap,
     % if method_default_scope(m):
 ## We need the scopes sorted, to not unnecessarily query new tokens
     ${api.properties.scopes}: BTreeMap<String, ()>
@@ -133,11 +157,19 @@ pub struct ${ThisType}
 impl${mb_tparams} ${CALL_BUILDER_MARKERT_TRAIT} for ${ThisType} {}
 
 impl${mb_tparams} ${ThisType} where ${', '.join(mb_type_bounds())} {
-% if api.get('no_upload_prefix') is not None and ThisType.startswith(api.no_upload_prefix):
+% if no_upload_variant:
 ${self._action_fn(c, resource, method, m, params, request_value, parts, doit_without_upload = True)}\
 % endif
 
 ${self._action_fn(c, resource, method, m, params, request_value, parts)}\
+% if is_paginated_method(m, response_schema):
+
+${self._pagination_fns(m, response_schema)}\
+% endif
+% if operation_results:
+
+${self._operation_fns(response_schema, operation_results)}\
+% endif
 
 ## SETTERS ###############
 % for p in params:
@@ -163,6 +195,56 @@ ${self._setter_fn(resource, method, m, p, part_prop, ThisType, c)}\
         self.${api.properties.params}.insert(name.as_ref().to_string(), value.as_ref().to_string());
         self
     }
+    % if supports_fields:
+
+    /// Select the fields of the `${response_schema.id}` the server should respond with, by setting the `fields`
+    /// parameter. Fields which are not selected keep their default value.
+    % if is_paginated_method(m, response_schema):
+    ///
+    /// Please note that the *${NEXT_PAGE_TOKEN_PROPERTY}* field must be selected for `pages()` to obtain more than
+    /// the first page.
+    % endif
+    pub fn ${FIELDS_FN}(mut self, fields: client::Fields<${response_schema.id}>) -> ${ThisType} {
+        self.${api.properties.params}.insert("fields".to_string(), fields.to_string());
+        self
+    }
+    % endif
+
+    /// Set an additional header of the request, replacing any value set before.
+    ///
+    /// Please note that this method must not be used to set headers which are set by the system, like
+    /// `Authorization` or `Content-Type`.
+    pub fn header<K>(mut self, name: K, value: hyper::header::HeaderValue) -> ${ThisType}
+                                                        where K: hyper::header::IntoHeaderName {
+        self.${api.properties.headers}.insert(name, value);
+        self
+    }
+
+    /// Only perform the call if the resource still has the given `etag`, e.g. to not overwrite changes made
+    /// by someone else in the meantime. Otherwise, the server fails the call with `412 Precondition Failed`.
+    pub fn if_match(self, etag: hyper::header::HeaderValue) -> ${ThisType} {
+        self.header(hyper::header::IF_MATCH, etag)
+    }
+
+    /// Only perform the call if the resource doesn't have the given `etag` anymore. Otherwise, the call fails
+    /// with `Error::NotModified`, meaning that a copy of the resource with that `etag` is still up to date.
+    pub fn if_none_match(self, etag: hyper::header::HeaderValue) -> ${ThisType} {
+        self.header(hyper::header::IF_NONE_MATCH, etag)
+    }
+    % if 'quota_project' in header_setters:
+
+    /// Bill the quota of this call to the given project, instead of the one set on the hub, see `${hub_type_name}::quota_project()`.
+    pub fn quota_project(self, project: hyper::header::HeaderValue) -> ${ThisType} {
+        self.header(client::QUOTA_PROJECT_HEADER, project)
+    }
+    % endif
+    % if 'request_reason' in header_setters:
+
+    /// Record the given reason for this call in the audit logs, instead of the one set on the hub, see `${hub_type_name}::request_reason()`.
+    pub fn request_reason(self, reason: hyper::header::HeaderValue) -> ${ThisType} {
+        self.header(client::REQUEST_REASON_HEADER, reason)
+    }
+    % endif
 
     % if method_default_scope(m):
     /// Identifies the authorization scope for the method you are building.
@@ -179,9 +261,9 @@ ${self._setter_fn(resource, method, m, p, part_prop, ThisType, c)}\
     /// Usually there is more than one suitable scope to authorize an operation, some of which may
     /// encompass more rights than others. For example, for listing resources, a *read-only* scope will be
     /// sufficient, a read-write scope will do as well.
-    pub fn ${ADD_SCOPE_FN}<T, S>(mut self, scope: T) -> ${ThisType}
-                                                        where T: Into<Option<S>>,
-                                                              S: AsRef<str> {
+    pub fn ${ADD_SCOPE_FN}<T, St>(mut self, scope: T) -> ${ThisType}
+                                                        where T: Into<Option<St>>,
+                                                              St: AsRef<str> {
         match scope.into() {
           Some(scope) => self.${api.properties.scopes}.insert(scope.as_ref().to_string(), ()),
           None => None,
@@ -190,6 +272,35 @@ ${self._setter_fn(resource, method, m, p, part_prop, ThisType, c)}\
     }
     % endif
 }
+% if supports_batch:
+
+impl${mb_tparams} client::BatchCall for ${ThisType} where ${', '.join(mb_type_bounds())} {
+    % if response_schema:
+    type Output = (hyper::Response<hyper::body::Body>, ${response_schema.id});
+    % else:
+    type Output = hyper::Response<hyper::body::Body>;
+    % endif
+
+    fn batch_request(mut self) -> client::Result<client::BatchRequest> {
+        self._batch_request()
+    }
+
+    fn batch_response(res: hyper::Response<hyper::body::Bytes>) -> client::Result<Self::Output> {
+        % if response_schema:
+        client::decode_json_response(res)
+        % else:
+        let (parts, body) = res.into_parts();
+        Ok(hyper::Response::from_parts(parts, hyper::body::Body::from(body)))
+        % endif
+    }
+}
+% endif
+% for p in params:
+% if 'enum_type' in p:
+
+${schema.new_enum(p.enum_type, p)}\
+% endif
+% endfor
 </%def>
 
 
@@ -279,7 +390,10 @@ ${self._setter_fn(resource, method, m, p, part_prop, ThisType, c)}\
         sp.repeated = prev
         return res
     # rvfrt = random value for rust type
-    rvfrt = lambda spn, sp, sn=None: rnd_arg_val_for_type(trv(spn, sp, sn))
+    def rvfrt(spn, sp, sn=None):
+        if 'enum_type' in sp:
+            return '%s::api::%s::%s' % (util.library_name(), sp.enum_type, choice(enum_variants(sp))[0])
+        return rnd_arg_val_for_type(trv(spn, sp, sn))
 
     rb_name = 'req'   # name of request binding
     required_args = request_value and [rb_name] or []
@@ -327,7 +441,7 @@ use ${util.library_name()}::api::${request_value_type};
 use ${util.library_name()}::{Result, Error};
 % endif
 % if media_params:
-use std::fs;
+use tokio::fs;
 % endif
 <%block filter="test_fn_filter">\
 ${capture(lib.test_hub, hub_type_name, comments=show_all) | hide_filter}
@@ -379,14 +493,21 @@ match result {
         // The Error enum provides details about what exactly happened.
         // You can also just use its `Debug`, `Display` or `Error` traits
          Error::HttpError(_)
+        |Error::Timeout(_)
         |Error::MissingAPIKey
         |Error::MissingToken(_)
         |Error::Cancelled
         |Error::UploadSizeLimitExceeded(_, _)
         |Error::Failure(_)
-        |Error::BadRequest(_)
+        |Error::NotModified(_)
+        |Error::Api(_)
         |Error::FieldClash(_)
-        |Error::JsonDecodeError(_, _) => println!("{}", e),
+        |Error::JsonDecodeError(_, _)
+        |Error::BatchResponse(_)
+        |Error::InvalidUploadSession(_)
+        |Error::Operation(_)
+        |Error::InvalidOperation(_)
+        |Error::InvalidEndpoint(_) => println!("{}", e),
     },
     Ok(res) => println!("Success: {:?}", res),
 }
@@ -437,20 +558,31 @@ match result {
                 simple_media_param = p
             elif p.protocol == 'resumable':
                 resumable_media_param = p
+        if resumable_media_param:
+            add_args += ', resume_session: Option<client::UploadSession>'
     # end handle media params
 
     if doit_without_upload:
-        action_fn = qualifier + 'async fn ' + "doit_without_upload" + type_params + '(mut self)' + ' -> ' + rtype + where
+        action_name = 'doit_without_upload'
     else:
-        action_fn = qualifier + 'async fn ' + api.terms.action + type_params + ('(mut self%s)' % add_args) + ' -> ' + rtype + where
+        action_name = api.terms.action
+    # The public action consumes the builder, whereas the one doing the actual work only borrows it.
+    # That way, the same call can be performed repeatedly, e.g. for each page of a paginated result.
+    exec_name = '_' + action_name
+    action_fn = qualifier + 'async fn ' + action_name + type_params + ('(mut self%s)' % add_args) + ' -> ' + rtype + where
+    exec_fn = 'async fn ' + exec_name + type_params + ('(&mut self%s)' % add_args) + ' -> ' + rtype + where
+    exec_args = media_params and 'reader, reader_mime_type, protocol' or ''
+    if resumable_media_param:
+        exec_args += ', resume_session'
 
     field_params = [p for p in params if p.get('is_query_param', True)]
 
     paddfields = 'self.' + api.properties.params
 
     delegate = 'self.' + property(DELEGATE_PROPERTY_NAME)
+    timeout = 'self.' + property(call_timeout_name(m))
     delegate_finish = 'dlg.finished'
-    auth_call = 'self.hub.auth.borrow_mut()'
+    auth_call = 'self.hub.auth'
 
     default_scope = method_default_scope(m)
 
@@ -461,7 +593,7 @@ match result {
     MULTI_SLASH = 'multi-slash-prefix'
     URL_ENCODE = 'url-encode'
 
-    READER_SEEK = "let size = reader.seek(io::SeekFrom::End(0)).unwrap();\nreader.seek(io::SeekFrom::Start(0)).unwrap();\n"
+    READER_SEEK = "let size = reader.seek(io::SeekFrom::End(0)).await.unwrap();\nreader.seek(io::SeekFrom::Start(0)).await.unwrap();\n"
     if media_params:
         max_size = media_params[0].max_size
         if max_size > 0:
@@ -494,25 +626,9 @@ match result {
     # end for each possible url
     del seen
 %>
-    % if doit_without_upload:
-    /// Perform the operation you have build so far, but without uploading. This is used to e.g. renaming or updating the description for a file
-    % else:
-    /// Perform the operation you have build so far.
-    % endif
-    ${action_fn} {
-        % if URL_ENCODE in special_cases:
-        use url::percent_encoding::{percent_encode, DEFAULT_ENCODE_SET};
-        % endif
-        use std::io::{Read, Seek};
-        use hyper::header::{CONTENT_TYPE, CONTENT_LENGTH, AUTHORIZATION, USER_AGENT, LOCATION};
-        use client::ToParts;
-        let mut dd = client::DefaultDelegate;
-        let mut dlg: &mut dyn client::Delegate = match ${delegate} {
-            Some(d) => d,
-            None => &mut dd
-        };
-        dlg.begin(client::MethodInfo { id: "${m.id}",
-                               http_method: ${method_name_to_variant(m.httpMethod)} });
+<%def name="build_url(finish=True)">\
+## Collects all parameters, and produces the final url of the call in `url`.
+## Used by the call itself, as well as by its batch request, which doesn't tell the delegate it finished.
         let mut params: Vec<(&str, String)> = Vec::with_capacity(${len(params) + len(reserved_params)} + ${paddfields}.len());
 <%
     if media_params and 'mediaUpload' in m:
@@ -553,7 +669,7 @@ match result {
             }
         }
         % elif not is_required_property(p):
-        if let Some(value) = ${pname} {
+        if let Some(value) = ${pname}.as_ref() {
             params.push(("${p.name}", value.to_string()));
         }
         % else:
@@ -563,7 +679,9 @@ match result {
         ## Additional params - may not overlap with optional params
         for &field in [${', '.join(enclose_in('"', reserved_params + [p.name for p in field_params]))}].iter() {
             if ${paddfields}.contains_key(field) {
+                % if finish:
                 ${delegate_finish}(false);
+                % endif
                 return Err(client::Error::FieldClash(field));
             }
         }
@@ -626,12 +744,17 @@ else {
         match key {
             Some(value) => params.push(("key", value)),
             None => {
+                % if finish:
                 ${delegate_finish}(false);
+                % endif
                 return Err(client::Error::MissingAPIKey)
             }
         }
         % endif
         % else:
+        if let Some(key) = ${auth_call}.api_key() {
+            params.push(("key", key));
+        }
         if self.${api.properties.scopes}.len() == 0 {
             self.${api.properties.scopes}.insert(${scope_url_to_variant(name, default_scope, fully_qualified=True)}.as_ref().to_string(), ());
         }
@@ -679,35 +802,69 @@ else {
         % endif
 
         let url = url::Url::parse_with_params(&url, params).unwrap();
+</%def>\
+    % if doit_without_upload:
+    /// Perform the operation you have build so far, but without uploading. This is used to e.g. renaming or updating the description for a file
+    % else:
+    /// Perform the operation you have build so far.
+    % endif
+    ${action_fn} {
+        use tracing::Instrument;
+        self.${exec_name}(${exec_args}).instrument(${self._call_span(m)}).await
+    }
+
+    ${exec_fn} {
+        % if URL_ENCODE in special_cases:
+        use url::percent_encoding::{percent_encode, DEFAULT_ENCODE_SET};
+        % endif
+        % if media_params:
+        use tokio::io::AsyncSeekExt;
+        % endif
+        use hyper::header::{CONTENT_TYPE, CONTENT_ENCODING, CONTENT_LENGTH, AUTHORIZATION, USER_AGENT, LOCATION};
+        use client::ToParts;
+        let mut dd = client::DefaultDelegate;
+        let mut dlg: &mut dyn client::Delegate = match ${delegate}.as_mut() {
+            Some(d) => &mut **d,
+            None => &mut dd
+        };
+        dlg.begin(client::MethodInfo { id: "${m.id}",
+                               http_method: ${method_name_to_variant(m.httpMethod)} });
+        let mut trace = client::CallTrace::current();
+        let timeout = ${timeout}.or(self.hub._timeout);
+        let headers = client::merge_headers(&self.hub._headers, &self.${api.properties.headers});
+${build_url()}\
 
         % if request_value:
         let mut json_mime_type: mime::Mime = "application/json".parse().unwrap();
-        let mut request_value_reader =
+        let request_body =
             {
                 let mut value = json::value::to_value(&self.${property(REQUEST_VALUE_PROPERTY_NAME)}).expect("serde to work");
                 client::remove_json_null_values(&mut value);
-                let mut dst = io::Cursor::new(Vec::with_capacity(128));
-                json::to_writer(&mut dst, &value).unwrap();
-                dst
+                json::to_vec(&value).unwrap()
             };
-        let request_size = request_value_reader.seek(io::SeekFrom::End(0)).unwrap();
-        request_value_reader.seek(io::SeekFrom::Start(0)).unwrap();
+        % if not media_params:
+        let (request_body, request_encoding) = match self.hub._compression.compress_request(&request_body) {
+            Some(compressed) => (compressed, Some("gzip")),
+            None => (request_body, None),
+        };
+        % endif
+        let request_size = request_body.len() as u64;
         % endif
 
         % if resumable_media_param:
         let mut should_ask_dlg_for_url = false;
         let mut upload_url_from_server;
         let mut upload_url: Option<String> = None;
+        let mut resume_session = resume_session;
         % endif
 
         loop {
             % if default_scope:
-            let authenticator = ${auth_call};
-            let token = match authenticator.token(&self.${api.properties.scopes}.keys().collect::<Vec<_>>()[..]).await {
-                Ok(token) => token.clone(),
+            let token = match ${auth_call}.get_token(&self.${api.properties.scopes}.keys().map(String::as_str).collect::<Vec<_>>()[..]).await {
+                Ok(token) => token,
                 Err(err) => {
-                    match  dlg.token(&err) {
-                        Some(token) => token,
+                    match  dlg.token(&*err) {
+                        Some(token) => Some(token),
                         None => {
                             ${delegate_finish}(false);
                             return Err(client::Error::MissingToken(err))
@@ -716,45 +873,81 @@ else {
                 }
             };
             % endif
-            % if request_value:
-            request_value_reader.seek(io::SeekFrom::Start(0)).unwrap();
-            % endif
+            let ${resumable_media_param and 'mut ' or ''}deadline = client::Deadline::start(timeout);
             let mut req_result = {
             % if resumable_media_param:
-                if should_ask_dlg_for_url && (upload_url = dlg.upload_url()) == () && upload_url.is_some() {
+                if let Some(session) = resume_session.take() {
+                    upload_url_from_server = false;
+                    Ok(Ok(hyper::Response::builder()
+                        .status(hyper::StatusCode::OK)
+                        .header(LOCATION, session.url)
+                        .body(hyper::body::Body::empty())
+                        .unwrap()))
+                } else if should_ask_dlg_for_url && (upload_url = dlg.upload_url()) == () && upload_url.is_some() {
                     should_ask_dlg_for_url = false;
                     upload_url_from_server = false;
-                    let url = upload_url.as_ref().and_then(|s| Some(url::Url::parse(s).unwrap())).unwrap();
-                    Ok(hyper::Response::builder()
+                    Ok(Ok(hyper::Response::builder()
                         .status(hyper::StatusCode::OK)
-                        .header("Localtion", upload_url.as_ref().unwrap().clone())
+                        .header(LOCATION, upload_url.as_ref().unwrap().clone())
                         .body(hyper::body::Body::empty())
-                        .unwrap())
+                        .unwrap()))
                 } else {
             % endif
 <%block filter="indent_by(resumable_media_param and 4 or 0)">\
-            % if request_value and simple_media_param:
+            % if simple_media_param:
+                % if request_value:
                 let mut mp_reader: client::MultiPartReader = Default::default();
-                let (mut body_reader, content_type) = match protocol {
+                let mut request_value_reader: &[u8] = &request_body;
+                % else:
+                let mut request_value_reader: &[u8] = &[];
+                % endif
+                let (body_reader, content_type, content_length) = match protocol {
                     "${simple_media_param.protocol}" => {
-                        mp_reader.reserve_exact(2);
                         ${READER_SEEK | indent_all_but_first_by(5)}
+                % if request_value:
+                        mp_reader.reserve_exact(2);
                         mp_reader.add_part(&mut request_value_reader, request_size, json_mime_type.clone())
                                  .add_part(&mut reader, size, reader_mime_type.clone());
                         let mime_type = mp_reader.mime_type();
-                        (&mut mp_reader as &mut dyn io::Read, (CONTENT_TYPE, format!("{}", mime_type)))
+                        let content_length = mp_reader.content_length();
+                        (&mut mp_reader as &mut (dyn tokio::io::AsyncRead + Unpin + Send), Some(format!("{}", mime_type)), content_length)
+                % else:
+                        (&mut reader as &mut (dyn tokio::io::AsyncRead + Unpin + Send), Some(format!("{}", reader_mime_type)), size)
+                % endif
                     },
-                    _ => (&mut request_value_reader as &mut dyn io::Read, (CONTENT_TYPE, format!("{}", json_mime_type))),
+                % if request_value:
+                    _ => (&mut request_value_reader as &mut (dyn tokio::io::AsyncRead + Unpin + Send), Some(format!("{}", json_mime_type)), request_size),
+                % else:
+                    _ => (&mut request_value_reader as &mut (dyn tokio::io::AsyncRead + Unpin + Send), None, 0),
+                % endif
                 };
+                let (body, body_stream) = client::body_from_reader(body_reader);
             % endif
-                let mut client = &mut *self.hub.client.borrow_mut();
+                let client = &self.hub.client;
                 dlg.pre_request();
+                % if simple_media_param:
+                trace.attempt(url.as_str(), content_length);
+                % elif request_value:
+                trace.attempt(url.as_str(), request_size);
+                % else:
+                trace.attempt(url.as_str(), 0);
+                % endif
                 let mut req_builder = hyper::Request::builder().method(${method_name_to_variant(m.httpMethod)}).uri(url.clone().into_string())
-                        .header(USER_AGENT, self.hub._user_agent.clone())\
-                        % if default_scope:
-                            .header(AUTHORIZATION, format!("Bearer {}", token.as_str()))\
-                        % endif
-;
+                        .header(USER_AGENT, self.hub._user_agent.clone())
+                        .extension(client::MethodInfo { id: "${m.id}", http_method: ${method_name_to_variant(m.httpMethod)} });
+                % if default_scope:
+                if let Some(ref token) = token {
+                    req_builder = req_builder.header(AUTHORIZATION, format!("Bearer {}", token));
+                }
+                % endif
+                for (name, value) in headers.iter() {
+                    req_builder = req_builder.header(name, value.clone());
+                }
+                % if response_schema:
+                if self.hub._compression.responses${supports_download and ' && enable_resource_parsing' or ''} {
+                    req_builder = client::accept_gzip(req_builder);
+                }
+                % endif
 
                 % if resumable_media_param:
                 upload_url_from_server = true;
@@ -763,37 +956,35 @@ else {
                 }
                 % endif
 
-                % if request_value:
-                    % if not simple_media_param:
+                % if simple_media_param:
+                        if let Some(content_type) = content_type {
+                            req_builder = req_builder.header(CONTENT_TYPE, content_type);
+                        }
+                        let request = req_builder
+                        .header(CONTENT_LENGTH, content_length)
+                        .body(body)\
+                % elif request_value:
+                        % if not media_params:
+                        if let Some(encoding) = request_encoding {
+                            req_builder = req_builder.header(CONTENT_ENCODING, encoding);
+                        }
+                        % endif
                         let request = req_builder
                         .header(CONTENT_TYPE, format!("{}", json_mime_type))
                         .header(CONTENT_LENGTH, request_size as u64)
-                        .body(hyper::body::Body::from(request_value_reader.get_ref().clone()))\
-                    % else:
-                        let mut body_reader_bytes = vec![];
-                        body_reader.read_to_end(&mut body_reader_bytes).unwrap();
-                        let request = req_builder
-                        .header(content_type.0, content_type.1)
-                        .body(hyper::body::Body::from(body_reader_bytes))\
-                    % endif ## not simple_media_param
+                        .body(hyper::body::Body::from(request_body.clone()))\
                 % else:
-                    % if simple_media_param:
-                        let request = if protocol == "${simple_media_param.protocol}" {
-                            ${READER_SEEK | indent_all_but_first_by(4)}
-                            req_builder.header(CONTENT_TYPE, reader_mime_type.clone())
-                                     .header(CONTENT_LENGTH, size)
-                                     .body(&mut reader);
-                        } else {
-                            req_builder.body(hyper::body::Body::empty())
-                        }\
-                    % else:
                         let request = req_builder
                         .body(hyper::body::Body::empty())\
-                    % endif
                 % endif
 ;
 
-                client.borrow_mut().request(request.unwrap()).await
+                % if simple_media_param:
+                let request = client.send(request.unwrap());
+                deadline.run(async { futures::join!(request, body_stream).0 }).await
+                % else:
+                deadline.run(client.send(request.unwrap())).await
+                % endif
                 
 </%block>\
                 % if resumable_media_param:
@@ -802,66 +993,85 @@ else {
             };
 
             match req_result {
-                Err(err) => {
-                    if let client::Retry::After(d) = dlg.http_error(&err) {
-                        sleep(d);
+                Err(timeout) => {
+                    trace.timeout(timeout);
+                    if let client::Retry::After(d) = dlg.timeout(timeout) {
+                        tokio::time::sleep(d).await;
+                        continue;
+                    }
+                    ${delegate_finish}(false);
+                    return Err(client::Error::Timeout(timeout))
+                }
+                Ok(Err(err)) => {
+                    trace.error(&*err);
+                    if let client::Retry::After(d) = dlg.http_error(&*err) {
+                        tokio::time::sleep(d).await;
                         continue;
                     }
                     ${delegate_finish}(false);
                     return Err(client::Error::HttpError(err))
                 }
-                Ok(mut res) => {
-                    let (res_parts, res_body) = res.into_parts();
-                    let res_body_string: String = String::from_utf8(
-                        hyper::body::to_bytes(res_body)
-                            .await
-                            .unwrap()
-                            .into_iter()
-                            .collect(),
-                    )
-                    .unwrap();
-                    let reconstructed_result =
-                        hyper::Response::from_parts(res_parts, res_body_string.clone().into());
-
-                    if !reconstructed_result.status().is_success() {
-                        let json_server_error = json::from_str::<client::JsonServerError>(&res_body_string).ok();
-                        let server_error = json::from_str::<client::ServerError>(&res_body_string)
-                            .or_else(|_| json::from_str::<client::ErrorResponse>(&res_body_string).map(|r| r.error))
-                            .ok();
-
-                        if let client::Retry::After(d) = dlg.http_failure(&reconstructed_result,
+                Ok(Ok(mut res)) => {
+                    trace.response(&res);
+                    if res.status() == hyper::StatusCode::NOT_MODIFIED {
+                        ${delegate_finish}(true);
+                        return Err(client::Error::NotModified(res))
+                    }
+                    if !res.status().is_success() {
+                        let (res, res_body) = match deadline.read_body(res).await {
+                            Ok(r) => r,
+                            Err(client::Error::Timeout(timeout)) => {
+                                trace.timeout(timeout);
+                                if let client::Retry::After(d) = dlg.timeout(timeout) {
+                                    tokio::time::sleep(d).await;
+                                    continue;
+                                }
+                                ${delegate_finish}(false);
+                                return Err(client::Error::Timeout(timeout))
+                            }
+                            Err(err) => {
+                                ${delegate_finish}(false);
+                                return Err(err)
+                            }
+                        };
+                        trace.received(res_body.len());
+                        let json_server_error = json::from_slice::<client::JsonServerError>(&res_body).ok();
+                        let api_error = client::ApiError::from_body(&res_body);
+
+                        if let client::Retry::After(d) = dlg.http_failure(&res,
                                                               json_server_error,
-                                                              server_error) {
-                            sleep(d);
+                                                              api_error.clone()) {
+                            tokio::time::sleep(d).await;
                             continue;
                         }
                         ${delegate_finish}(false);
-                        return match json::from_str::<client::ErrorResponse>(&res_body_string){
-                            Err(_) => Err(client::Error::Failure(reconstructed_result)),
-                            Ok(serr) => Err(client::Error::BadRequest(serr))
+                        return match api_error {
+                            None => Err(client::Error::Failure(res)),
+                            Some(err) => Err(client::Error::Api(err))
                         }
                     }
                     % if resumable_media_param:
                     if protocol == "${resumable_media_param.protocol}" {
                         ${READER_SEEK | indent_all_but_first_by(6)}
-                        let mut client = &mut *self.hub.client.borrow_mut();
                         let upload_result = {
-                            let url_str = &reconstructed_result.headers().get("Location").expect("LOCATION header is part of protocol").to_str().unwrap();
+                            let url_str = &res.headers().get("Location").expect("LOCATION header is part of protocol").to_str().unwrap();
                             if upload_url_from_server {
                                 dlg.store_upload_url(Some(url_str));
                             }
 
                             client::ResumableUploadHelper {
-                                client: &mut client.borrow_mut(),
+                                client: &self.hub.client,
                                 delegate: dlg,
                                 start_at: if upload_url_from_server { Some(0) } else { None },
-                                auth: &mut *self.hub.auth.borrow_mut(),
                                 user_agent: &self.hub._user_agent,
-                                auth_header: format!("Bearer {}", token.as_str()),
+                                auth_header: token.as_ref().map(|token| format!("Bearer {}", token)),
                                 url: url_str,
                                 reader: &mut reader,
                                 media_type: reader_mime_type.clone(),
-                                content_length: size
+                                content_length: size,
+                                chunk_timeout: self.hub._upload_chunk_timeout,
+                                method_info: client::MethodInfo { id: "${m.id}", http_method: ${method_name_to_variant(m.httpMethod)} },
+                                headers: client::upload_headers(&headers),
                             }.upload().await
                         };
                         match upload_result {
@@ -872,12 +1082,14 @@ else {
                             Some(Err(err)) => {
                                 ## Do not ask the delgate again, as it was asked by the helper !
                                 ${delegate_finish}(false);
-                                return Err(client::Error::HttpError(err))
+                                return Err(err)
                             }
                             ## Now the result contains the actual resource, if any ... it will be
                             ## decoded next
                             Some(Ok(upload_result)) => {
                                 res = upload_result;
+                                ## the upload may have taken long, reading the response gets a deadline of its own
+                                deadline = client::Deadline::start(timeout);
                                 if !res.status().is_success() {
                                     ## delegate was called in upload() already - don't tell him again
                                     dlg.store_upload_url(None);
@@ -895,20 +1107,38 @@ else {
 if enable_resource_parsing \
                     % endif
 {
-                        match json::from_str(&res_body_string) {
-                            Ok(decoded) => (reconstructed_result, decoded),
+                        let (res, res_body) = match deadline.read_body(res).await {
+                            Ok(r) => r,
+                            Err(client::Error::Timeout(timeout)) => {
+                                trace.timeout(timeout);
+                                if let client::Retry::After(d) = dlg.timeout(timeout) {
+                                    tokio::time::sleep(d).await;
+                                    continue;
+                                }
+                                ${delegate_finish}(false);
+                                return Err(client::Error::Timeout(timeout))
+                            }
+                            Err(err) => {
+                                ${delegate_finish}(false);
+                                return Err(err)
+                            }
+                        };
+                        trace.received(res_body.len());
+                        match json::from_slice(&res_body) {
+                            Ok(decoded) => (res, decoded),
                             Err(err) => {
+                                let res_body_string = String::from_utf8_lossy(&res_body).into_owned();
                                 dlg.response_json_decode_error(&res_body_string, &err);
                                 return Err(client::Error::JsonDecodeError(res_body_string, err));
                             }
                         }
                     }\
                     % if supports_download:
- else { (reconstructed_result, Default::default()) }\
+ else { (res, Default::default()) }\
                     % endif
 ;
                 % else:
-                    let result_value = reconstructed_result;
+                    let result_value = res;
                 % endif
 
                     ${delegate_finish}(true);
@@ -918,6 +1148,54 @@ if enable_resource_parsing \
         }
     }
 
+    % if context.get('batchPath') and not media_params:
+
+    /// Build the request this call would make, to send it as part of a batch request.
+    fn _batch_request(&mut self) -> client::Result<client::BatchRequest> {
+        % if URL_ENCODE in special_cases:
+        use url::percent_encoding::{percent_encode, DEFAULT_ENCODE_SET};
+        % endif
+        use client::ToParts;
+        % if not default_scope and no_auth is UNDEFINED:
+        ## the delegate may provide the API key, but is told about the call only when the batch is sent, if at all
+        let mut dd = client::DefaultDelegate;
+        let mut dlg: &mut dyn client::Delegate = match ${delegate}.as_mut() {
+            Some(d) => &mut **d,
+            None => &mut dd
+        };
+        % endif
+${build_url(finish=False)}\
+        % if response_schema and supports_download:
+        // media can't be downloaded as part of a batch, the response is always decoded as json
+        let _ = enable_resource_parsing;
+        % endif
+        % if request_value:
+        let request_body =
+            {
+                let mut value = json::value::to_value(&self.${property(REQUEST_VALUE_PROPERTY_NAME)}).expect("serde to work");
+                client::remove_json_null_values(&mut value);
+                json::to_vec(&value).unwrap()
+            };
+        % endif
+
+        Ok(client::BatchRequest {
+            method: ${method_name_to_variant(m.httpMethod)},
+            url: url.into_string(),
+            % if default_scope:
+            scopes: self.${api.properties.scopes}.keys().cloned().collect(),
+            % else:
+            scopes: Vec::new(),
+            % endif
+            headers: client::merge_headers(&self.hub._headers, &self.${api.properties.headers}),
+            % if request_value:
+            body: Some(request_body),
+            % else:
+            body: None,
+            % endif
+        })
+    }
+    % endif
+
     % for p in media_params:
     ${p.description | rust_doc_sanitize, rust_doc_comment, indent_all_but_first_by(1)}
     ///
@@ -926,7 +1204,109 @@ if enable_resource_parsing \
     % endfor
     pub async fn ${upload_action_fn(api.terms.upload_action, p.type.suffix)}<${mtype_param}>(self, ${p.type.arg_name}: ${mtype_param}, mime_type: mime::Mime) -> ${rtype}
                 where ${mtype_param}: client::ReadSeek {
-        self.${api.terms.action}(${p.type.arg_name}, mime_type, "${p.protocol}").await
+        self.${api.terms.action}(${p.type.arg_name}, mime_type, "${p.protocol}"${resumable_media_param and ', None' or ''}).await
     }
     % endfor
+    % if resumable_media_param:
+
+    /// Continue a resumable upload, which was interrupted or cancelled, using the session previously handed to
+    /// `Delegate::store_upload_session()`. The server is asked how many bytes it received, and the upload
+    /// continues from there. The given stream must provide the same media the upload was started with.
+    ///
+    /// All other properties of this call are ignored, as they were sent when starting the upload.
+    pub async fn ${upload_action_fn(api.terms.upload_action, resumable_media_param.type.suffix)}_from_session<${mtype_param}>(self, session: client::UploadSession, mut ${resumable_media_param.type.arg_name}: ${mtype_param}) -> ${rtype}
+                where ${mtype_param}: client::ReadSeek {
+        use tokio::io::AsyncSeekExt;
+        let size = ${resumable_media_param.type.arg_name}.seek(io::SeekFrom::End(0)).await.unwrap();
+        session.check("${m.id}", size)?;
+        let mime_type: mime::Mime = match session.mime_type.parse() {
+            Ok(mime_type) => mime_type,
+            Err(_) => return Err(client::Error::InvalidUploadSession(format!("invalid mime type {}", session.mime_type))),
+        };
+        self.${api.terms.action}(${resumable_media_param.type.arg_name}, mime_type, "${resumable_media_param.protocol}", Some(session)).await
+    }
+    % endif
+</%def>
+
+
+## The tracing span a call of the given method is made in
+###############################################################################################
+###############################################################################################
+<%def name="_call_span(m)">\
+client::call_span("${m.id}", &${method_name_to_variant(m.httpMethod)}, "${m.path}")\
+</%def>
+
+
+## create the functions to iterate all pages of a paginated method, along with all of their items
+###############################################################################################
+###############################################################################################
+<%def name="_pagination_fns(m, response_schema)">\
+<%
+    rtype = 'client::Result<(hyper::Response<hyper::body::Body>, %s)>' % response_schema.id
+    item_prop = pagination_item_property(response_schema)
+    if item_prop:
+        item_name, item_p = item_prop
+        item_type = to_rust_type(schemas, response_schema.id, item_name, item_p, allow_optionals=False)
+        assert item_type.startswith('Vec<')
+        item_type = item_type[len('Vec<'):-1]
+%>\
+    /// Return a stream of all pages of the result, starting at the currently set page token.
+    ///
+    /// Each page is obtained with a separate call to the server once the previous one was consumed, feeding
+    /// its *${split_camelcase_s(NEXT_PAGE_TOKEN_PROPERTY)}* back into the *${split_camelcase_s(PAGE_TOKEN_PARAM)}*.
+    /// The stream ends after the last page, or right after the first error.
+    pub fn pages(self) -> impl futures::Stream<Item = ${rtype}> + 'a {
+        use tracing::Instrument;
+        futures::stream::unfold(Some(self), |call| async move {
+            let mut call = call?;
+            let result = call.${'_' + api.terms.action}().instrument(${self._call_span(m)}).await;
+            let next_call = match result {
+                Ok((_, ref page)) => match page.${mangle_ident(NEXT_PAGE_TOKEN_PROPERTY)} {
+                    Some(ref token) if !token.is_empty() => {
+                        call.${property(PAGE_TOKEN_PARAM)} = Some(token.clone());
+                        Some(call)
+                    }
+                    _ => None,
+                },
+                Err(_) => None,
+            };
+            Some((result, next_call))
+        })
+    }
+    % if item_prop:
+
+    /// Return a stream of all *${split_camelcase_s(item_name)}* of all pages, which are requested on demand.
+    ///
+    /// See `pages()` for more information.
+    pub fn items(self) -> impl futures::Stream<Item = client::Result<${item_type}>> + 'a {
+        use futures::TryStreamExt;
+        self.pages()
+            .map_ok(|(_, page)| futures::stream::iter(page.${mangle_ident(item_name)}.unwrap_or_default().into_iter().map(Ok)))
+            .try_flatten()
+    }
+    % endif
+</%def>
+
+
+## create the function to wait for the long-running operation a method starts, decoding its results
+## 'results' is [(field, rust_type), ...]
+###############################################################################################
+###############################################################################################
+<%def name="_operation_fns(response_schema, results)">\
+<%
+    rtype = 'client::Result<(%s, %s)>' % (response_schema.id, ', '.join('Option<%s>' % t for _, t in results))
+%>\
+    /// Perform the operation you have build so far, and wait until the long-running operation it starts is done,
+    /// see `${response_schema.id}::wait()`.
+    ///
+    /// Returns the done operation along with its ${put_and(['*%s* decoded as `%s`' % r for r in results])}.
+    pub async fn ${api.terms.action}_and_wait(self, options: &client::PollOptions) -> ${rtype} {
+        let hub = self.hub;
+        let (_, operation) = self.${api.terms.action}().await?;
+        let operation = operation.wait(hub, options).await?;
+        % for pn, _ in results:
+        let ${mangle_ident(pn)} = operation.${mangle_ident(pn)}_as()?;
+        % endfor
+        Ok((operation, ${', '.join(mangle_ident(pn) for pn, _ in results)}))
+    }
 </%def>
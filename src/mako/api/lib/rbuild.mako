@@ -6,7 +6,10 @@
                       rust_copy_value_s, organize_params, REQUEST_VALUE_PROPERTY_NAME,
                       build_all_params, rb_type_params_s, hub_type_params_s, mb_type_params_s, mb_additional_type_params, 
                       struct_type_bounds_s, METHODS_RESOURCE, SPACES_PER_TAB, prefix_all_but_first_with,
-                      METHODS_BUILDER_MARKER_TRAIT, remove_empty_lines, method_default_scope, rust_doc_sanitize)
+                      METHODS_BUILDER_MARKER_TRAIT, remove_empty_lines, method_default_scope, rust_doc_sanitize,
+                      resource_trait_type, mock_params_type, mockable_methods, activity_rust_type, method_response,
+                      hub_type_bounds, call_timeout_name, method_media_params, DELEGATE_PROPERTY_NAME,
+                      MOCKABLE_FEATURE)
 %>\
 <%namespace name="util" file="../../lib/util.mako"/>\
 <%namespace name="lib" file="lib.mako"/>\
@@ -70,7 +73,7 @@ impl${rb_params} ${ThisType} {
     mb_tparams = mb_type_params_s(m)
     # we would could have information about data requirements for each property in it's dict.
     # for now, we just hardcode it, and treat the entries as way to easily change param names
-    assert len(api.properties) == 2, "Hardcoded for now, thanks to scope requirements"
+    assert len(api.properties) == 3, "Hardcoded for now, thanks to scope requirements"
 
     type_params = ''
     if mb_additional_type_params(m):
@@ -130,3 +133,97 @@ impl${rb_params} ${ThisType} {
     % endfor ## for each activity
 }
 </%def>
+
+
+## Creates the trait of a resource, which allows to mock its methods, along with the structs holding their parameters
+###############################################################################################
+###############################################################################################
+<%def name="mockable(resource, c)">\
+<%
+    hub_type_name = hub_type(schemas, util.canonical_name())
+    trait_name = resource_trait_type(resource)
+    methods = list()
+    for a in mockable_methods(c, resource, api.get('no_upload_prefix')):
+        m = c.fqan_map[to_fqan(c.rtc_map[resource], resource, a)]
+        action_name = method_media_params(m) and 'doit_without_upload' or api.terms.action
+        params, request_value = build_all_params(c, m)
+        # the delegate and timeout of a call are not what a mock is about
+        call_only = (DELEGATE_PROPERTY_NAME, call_timeout_name(m))
+        response_schema = method_response(c, m)
+        methods.append((a, m, params, [p for p in params if p.name not in call_only],
+                        response_schema and response_schema.id or '()', action_name))
+    # end for each mockable method
+%>\
+% for a, m, params, mock_params, response_type, action_name in methods:
+/// The parameters of the *${a}* method of the `${trait_name}` trait, as set using the setters of the `${mb_type(resource, a)}`.
+#[cfg(feature = "${MOCKABLE_FEATURE}")]
+#[derive(Clone, Debug, Default)]
+pub struct ${mock_params_type(resource, a)} {
+    % for p in mock_params:
+    % if p.name == REQUEST_VALUE_PROPERTY_NAME:
+    /// The value to send to the server.
+    % else:
+    ${p.get('description', 'No description provided.') | rust_doc_sanitize, rust_doc_comment, indent_all_but_first_by(1)}
+    % endif
+    pub ${mangle_ident(p.name)}: ${activity_rust_type(schemas, p, allow_optionals=not is_required_property(p))},
+    % endfor
+}
+
+% endfor
+% if resource == METHODS_RESOURCE:
+/// The methods which are not associated with a particular resource, as trait.
+% else:
+/// The methods supported on *${singular(resource)}* resources, as trait.
+% endif
+///
+/// It is implemented by the `${hub_type_name}` hub, and allows substituting it with a mock or fake in tests.
+/// Each method takes its parameters as struct, and returns the value the server responded with.
+/// Methods which can only be called with an upload of media are not part of it.
+#[cfg(feature = "${MOCKABLE_FEATURE}")]
+#[async_trait::async_trait]
+pub trait ${trait_name}: Send + Sync {
+    % for a, m, params, mock_params, response_type, action_name in methods:
+    % if not loop.first:
+
+    % endif
+    % if 'description' in m:
+    ${m.description | rust_doc_sanitize, rust_doc_comment, indent_all_but_first_by(1)}
+    % endif
+    async fn ${mangle_ident(a)}(&self, params: ${mock_params_type(resource, a)}) -> client::Result<${response_type}>;
+    % endfor
+}
+
+#[cfg(feature = "${MOCKABLE_FEATURE}")]
+#[async_trait::async_trait]
+impl${hub_type_params_s()} ${trait_name} for ${hub_type_name}${hub_type_params_s()}
+    where ${', '.join(hub_type_bounds())} {
+    % for a, m, params, mock_params, response_type, action_name in methods:
+    % if not loop.first:
+
+    % endif
+    async fn ${mangle_ident(a)}(&self, ${mock_params and 'params' or '_params'}: ${mock_params_type(resource, a)}) -> client::Result<${response_type}> {
+        let call = ${mb_type(resource, a)} {
+            hub: self,
+            % for p in params:
+            % if p in mock_params:
+            ${property(p.name)}: params.${mangle_ident(p.name)},
+            % else:
+            ${property(p.name)}: Default::default(),
+            % endif
+            % endfor
+            % for prop_key, custom_name in api.properties.items():
+            % if prop_key == 'scopes' and not method_default_scope(m):
+<% continue %>\
+            % endif
+            ${custom_name}: Default::default(),
+            % endfor
+        };
+        % if response_type == '()':
+        call.${action_name}().await.map(|_| ())
+        % else:
+        call.${action_name}().await.map(|(_, value)| value)
+        % endif
+    }
+    % endfor
+}
+</%def>
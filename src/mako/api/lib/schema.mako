@@ -3,12 +3,33 @@
                       IO_TYPES, activity_split, enclose_in, REQUEST_MARKER_TRAIT, mb_type, indent_all_but_first_by,
                       NESTED_TYPE_SUFFIX, RESPONSE_MARKER_TRAIT, split_camelcase_s, METHODS_RESOURCE,
                       PART_MARKER_TRAIT, canonical_type_name, TO_PARTS_MARKER, UNUSED_TYPE_MARKER, is_schema_with_optionals,
-                      rust_doc_sanitize, items)
+                      rust_doc_sanitize, items, schema_enums, enum_variants, escape_rust_string,
+                      ENUM_UNKNOWN_VARIANT, serde_as_type, field_selectors, FIELDS_FN, hub_type_bounds)
 %>\
+## Emit the serde attributes of a field whose json representation differs from its rust type, like
+## 64 bit integers which are transmitted as strings.
+###################################################################################################################
+###################################################################################################################
+<%def name="serde_with(p, allow_optionals, inline=False)">\
+<%
+    sat = serde_as_type(p, allow_optionals=allow_optionals)
+    attrs = list()
+    if sat and sat.startswith('Option<'):
+        attrs.append('#[serde(default)]')
+    if sat:
+        attrs.append('#[serde(with = "client::serde::As::<%s>")]' % sat)
+%>\
+% if inline:
+${''.join(a + ' ' for a in attrs)}\
+% else:
+${''.join('    %s\n' % a for a in attrs)}\
+% endif
+</%def>
+
 ## Build a schema which must be an object
 ###################################################################################################################
 ###################################################################################################################
-<%def name="_new_object(s, properties, c, allow_optionals)">\
+<%def name="_new_object(s, properties, c, allow_optionals, json_values)">\
 <% struct = 'pub struct ' + s.id %>\
 % if properties:
 ${struct} {
@@ -17,11 +38,12 @@ ${struct} {
     % if pn != mangle_ident(pn):
     #[serde(rename="${pn}")]
     % endif
-    pub ${mangle_ident(pn)}: ${to_rust_type(schemas, s.id, pn, p, allow_optionals=allow_optionals)},
+${serde_with(p, allow_optionals)}\
+    pub ${mangle_ident(pn)}: ${to_rust_type(schemas, s.id, pn, p, allow_optionals=allow_optionals, json_values=json_values)},
 % endfor
 }
 % elif 'additionalProperties' in s:
-${struct}(${to_rust_type(schemas, s.id, NESTED_TYPE_SUFFIX, s, allow_optionals=allow_optionals)});
+${struct}(${serde_with(s, allow_optionals, inline=True)}${to_rust_type(schemas, s.id, NESTED_TYPE_SUFFIX, s, allow_optionals=allow_optionals)});
 % elif 'variant' in s:
 <% 
     et = s.id
@@ -52,7 +74,7 @@ ${struct} { _never_set: Option<bool> }
 ## 's' contains the schema structure from json to build
 ###################################################################################################################
 ###################################################################################################################
-<%def name="new(s, c)">\
+<%def name="new(s, c, json_values=())">\
 <% 
     markers = schema_markers(s, c, transitive=True)
     # We always need Serialization support, as others might want to serialize the response, even though we will 
@@ -78,12 +100,12 @@ ${doc(s, c)}\
 </%block>
 #[derive(${', '.join(traits)})]
 % if s.type == 'object':
-${_new_object(s, s.get('properties'), c, allow_optionals)}\
+${_new_object(s, s.get('properties'), c, allow_optionals, json_values)}\
 % elif s.type == 'array':
 % if s.items.get('type') != 'object':
-pub struct ${s_type}(${to_rust_type(schemas, s.id, NESTED_TYPE_SUFFIX, s, allow_optionals=allow_optionals)});
+pub struct ${s_type}(${serde_with(s, allow_optionals, inline=True)}${to_rust_type(schemas, s.id, NESTED_TYPE_SUFFIX, s, allow_optionals=allow_optionals)});
 % else:
-${_new_object(s, s.items.get('properties'), c, allow_optionals)}\
+${_new_object(s, s.items.get('properties'), c, allow_optionals, json_values)}\
 % endif ## array item != 'object'
 % elif s.type == 'any':
 ## waiting for Default: https://github.com/rust-lang/rustc-serialize/issues/71
@@ -104,6 +126,10 @@ impl ${marker_trait} for ${s_type} {}
 % endif
 % endfor
 
+% for et, ep in schema_enums(s):
+${new_enum(et, ep)}
+% endfor
+
 % if TO_PARTS_MARKER in nt_markers and allow_optionals:
 impl ${TO_PARTS_MARKER} for ${s_type} {
     /// Return a comma separated list of members that are currently set, i.e. for which `self.member.is_some()`.
@@ -127,6 +153,187 @@ impl ${TO_PARTS_MARKER} for ${s_type} {
     }
 }
 % endif
+<% selectors = 'variant' not in s and field_selectors(schemas, s, c) or [] %>\
+% if selectors:
+
+impl ${s_type} {
+    /// Returns a selector for the fields of a `${s_type}`, to request a partial response by passing it to the
+    /// `${FIELDS_FN}()` method of a call builder, or to build an update mask.
+    pub fn ${FIELDS_FN}() -> client::Fields<${s_type}> {
+        Default::default()
+    }
+}
+
+impl client::Fields<${s_type}> {
+    % for pn, fn_name, nested_fn_name, nested_type in selectors:
+    % if not loop.first:
+
+    % endif
+    /// Select the *${pn}* field.
+    pub fn ${fn_name}(mut self) -> Self {
+        self.selector = self.selector.field("${pn}");
+        self
+    }
+    % if nested_fn_name:
+
+    /// Select the given fields of the *${pn}* field.
+    pub fn ${nested_fn_name}(mut self, fields: client::Fields<${nested_type}>) -> Self {
+        self.selector = self.selector.nested("${pn}", fields.selector);
+        self
+    }
+    % endif
+    % endfor
+}
+% endif
+</%def>
+
+## Create an enum for a string property with a fixed set of values.
+## 'et' is the name of the enum type, 'p' the property dict with the 'enum' values.
+###################################################################################################################
+###################################################################################################################
+<%def name="new_enum(et, p)">\
+<%
+    variants = enum_variants(p)
+    literal = lambda v: '"%s"' % escape_rust_string(v.replace('\\', '\\\\'))
+%>\
+<%block filter="rust_doc_sanitize, rust_doc_comment">\
+${p.get('description', 'There is no detailed description.')}
+
+Values unknown to this crate are represented by the `${ENUM_UNKNOWN_VARIANT}` variant, which allows them to roundtrip unchanged.
+</%block>
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ${et} {
+% for vn, value, desc in variants:
+    ${desc | rust_doc_sanitize, rust_doc_comment, indent_all_but_first_by(1)}
+    #[serde(rename=${literal(value)})]
+    ${vn},
+% endfor
+    /// A value this version of the crate doesn't know about.
+    #[serde(untagged)]
+    ${ENUM_UNKNOWN_VARIANT}(String),
+}
+
+impl AsRef<str> for ${et} {
+    fn as_ref(&self) -> &str {
+        match *self {
+% for vn, value, desc in variants:
+            ${et}::${vn} => ${literal(value)},
+% endfor
+            ${et}::${ENUM_UNKNOWN_VARIANT}(ref value) => value,
+        }
+    }
+}
+
+impl<'a> ${"From<&'a str>"} for ${et} {
+    fn from(value: &'a str) -> ${et} {
+        match value {
+% for vn, value, desc in variants:
+            ${literal(value)} => ${et}::${vn},
+% endfor
+            _ => ${et}::${ENUM_UNKNOWN_VARIANT}(value.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ${et} {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_ref())
+    }
+}
+
+impl Default for ${et} {
+    fn default() -> ${et} {
+        ${et}::${variants[0][0]}
+    }
+}
+</%def>
+
+## Create the methods to poll a long-running operation until it is done.
+## 'op' is the util.OperationPoller describing it.
+#########################################################################################################
+#########################################################################################################
+<%def name="operation(op, hub_type_name)">\
+<%
+    source = mangle_ident(op.source)
+    http_status = op.http_status and 'self.%s.map(|code| code as u16)' % mangle_ident(op.http_status) or 'None'
+
+    def getter_call(resource, method, args):
+        names = [a[1] is None and '&name' or mangle_ident(a[0]) for a in args]
+        return 'hub.%s().%s(%s).doit().await.map(|(_, operation)| operation)' % (
+                                            mangle_ident(resource), mangle_ident(method), ', '.join(names))
+%>\
+impl ${op.schema} {
+    /// Returns true if the operation finished, successfully or not.
+    pub fn is_done(&self) -> bool {
+        ${op.done}
+    }
+% if op.error:
+
+    /// The error the operation failed with, if it is done and failed.
+    pub fn failure(&self) -> Option<client::ApiError> {
+        let error = json::to_value(self.error.as_ref()?).ok()?;
+        client::ApiError::from_operation_error(&error, ${http_status})
+    }
+% endif
+% for pn in op.results:
+
+    /// Decode the *${pn}* of the operation into the given type, which the documentation of the method
+    /// starting the operation usually names.
+    pub fn ${mangle_ident(pn)}_as<T: serde::de::DeserializeOwned>(&self) -> client::Result<Option<T>> {
+        self.${mangle_ident(pn)}.as_ref().map(client::decode_any).transpose()
+    }
+% endfor
+
+    /// Poll the operation until it is done, getting its latest state from the server using the given hub.
+    ///
+    /// The returned operation may have failed, use `wait()` to fail with its error instead.
+    pub async fn poll_until_done<S>(self, hub: &${hub_type_name}<S>, options: &client::PollOptions) -> client::Result<${op.schema}>
+        where ${', '.join(hub_type_bounds())} {
+        client::poll_until_done(self, options, ${op.schema}::is_done, |operation| {
+            let name = operation.${source}.clone().unwrap_or_default();
+            async move {
+% for resource, method, args in op.getters:
+<%
+    ids = [(mangle_ident(pn), coll) for pn, coll, _ in args if coll is not None]
+    patterns = [escape_rust_string(pattern.replace('\\', '\\\\')) for _, coll, pattern in args
+                if coll is None and pattern]
+%>\
+                % for pattern in patterns:
+                if client::matches_name_pattern(&name, "${pattern}") {
+                % endfor
+                % if len(ids) == 1:
+                if let Some(${ids[0][0]}) = client::resource_id(&name, "${ids[0][1]}") {
+                % elif ids:
+                if let (${', '.join('Some(%s)' % i for i, _ in ids)}) = (${', '.join('client::resource_id(&name, "%s")' % coll for _, coll in ids)}) {
+                % endif
+                % if not (ids or patterns):
+                if !name.is_empty() {
+                % endif
+                    return ${getter_call(resource, method, args)};
+                % for _ in range(len(patterns) + (ids and 1 or 0) + (not (ids or patterns) and 1 or 0)):
+                }
+                % endfor
+% endfor
+                Err(client::Error::InvalidOperation(format!("no method gets the operation {:?}", name)))
+            }
+        }).await
+    }
+
+    /// Wait until the operation is done, like `poll_until_done()`, and fail with `Error::Operation` if it failed.
+    pub async fn wait<S>(self, hub: &${hub_type_name}<S>, options: &client::PollOptions) -> client::Result<${op.schema}>
+        where ${', '.join(hub_type_bounds())} {
+        let operation = self.poll_until_done(hub, options).await?;
+% if op.error:
+        match operation.failure() {
+            Some(err) => Err(client::Error::Operation(err)),
+            None => Ok(operation),
+        }
+% else:
+        Ok(operation)
+% endif
+    }
+}
 </%def>
 
 #########################################################################################################
@@ -2,7 +2,7 @@
 <%!
     from util import (hub_type, mangle_ident, indent_all_but_first_by, activity_rust_type, setter_fn_name, ADD_PARAM_FN,
                       upload_action_fn, is_schema_with_optionals, schema_markers, indent_by, method_default_scope,
-                      ADD_SCOPE_FN, TREF, enclose_in)
+                      ADD_SCOPE_FN, TREF, enclose_in, is_enum_property)
     from cli import (mangle_subcommand, new_method_context, PARAM_FLAG, STRUCT_FLAG, OUTPUT_FLAG, VALUE_ARG,
                      CONFIG_DIR, SCOPE_FLAG, is_request_value_property, FIELD_SEP, docopt_mode, FILE_ARG, MIME_ARG, OUT_ARG,
                      call_method_ident, POD_TYPES, opt_value, ident, JSON_TYPE_VALUE_MAP,
@@ -48,8 +48,7 @@ enum DoitError {
 
 struct Engine<'n> {
     opt: ArgMatches<'n>,
-    hub: ${hub_type_name}<hyper::Client<hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>, hyper::body::Body>
-    >,
+    hub: ${hub_type_name}<hyper::Client<hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>, hyper::body::Body>>,
     gp: ${"Vec<&'static str>"},
     gpm: Vec<(&'static str, &'static str)>,
 }
@@ -208,8 +207,12 @@ let ${prop_name}: ${prop_type} = arg_from_str(&${opt_value(p.name)}, err, "<${ma
         borrow = borrow_prefix(p)
         arg_name = mangle_ident(p.name)
         if p.get('type', '') == 'string':
-            if p.get('repeated', False):
+            if p.get('repeated', False) and is_enum_property(p):
+                arg_name = opt_values(p.name) + '.map(|&v| v.into()).collect::<Vec<_>>()'
+            elif p.get('repeated', False):
                 arg_name = opt_values(p.name) + '.map(|&v| v.to_string()).collect::<Vec<String>>()'
+            elif is_enum_property(p):
+                arg_name = opt_value(p.name) + '.into()'
             else:
                 arg_name = opt_value(p.name)
         call_args.append(borrow + arg_name)
@@ -237,6 +240,8 @@ for parg in ${opt_values(VALUE_ARG)} {
             call = call.${mangle_ident(setter_fn_name(p))}(\
         % if ptype != 'string':
 arg_from_str(${value_unwrap}, err, "${mangle_subcommand(p.name)}", "${ptype}")\
+        % elif is_enum_property(p):
+${value_unwrap}.into()\
         % else:
 ${value_unwrap}\
         % endif # handle conversion
@@ -300,7 +305,7 @@ if dry_run {
     match match protocol {
         % if mc.media_params:
         % for p in mc.media_params:
-        CallType::Upload(UploadProtocol::${p.protocol.capitalize()}) => call.${upload_action_fn(api.terms.upload_action, p.type.suffix)}(input_file.unwrap(), mime_type.unwrap()),
+        CallType::Upload(UploadProtocol::${p.protocol.capitalize()}) => call.${upload_action_fn(api.terms.upload_action, p.type.suffix)}(tokio::fs::File::from_std(input_file.unwrap()), mime_type.unwrap()),
         % endfor
         CallType::Standard => unreachable!()
         % else:
@@ -31,6 +31,21 @@ TYPE_MAP = {'boolean' : 'bool',
             'string'  : 'String',
             'object'  : 'HashMap'}
 
+# Schema properties of type 'string' with one of these formats are decoded into a more suitable type.
+# Parameters are not affected, as they are passed to the server as strings anyway.
+STRING_FORMAT_TYPE_MAP = {'int64'           : 'i64',
+                          'uint64'          : 'u64',
+                          'byte'            : 'Vec<u8>',
+                          'google-datetime' : 'client::chrono::DateTime<client::chrono::offset::Utc>',
+                          'google-duration' : 'client::chrono::Duration'}
+
+# The client::serde adapter to use for (de)serializing values of the given format.
+# Datetimes are RFC3339 strings, which chrono handles on its own.
+SERDE_AS_FORMAT_MAP = {'int64'           : 'client::serde::Str',
+                       'uint64'          : 'client::serde::Str',
+                       'byte'            : 'client::serde::Base64',
+                       'google-duration' : 'client::serde::Duration'}
+
 RESERVED_WORDS = set(('abstract', 'alignof', 'as', 'become', 'box', 'break', 'const', 'continue', 'crate', 'do',
                       'else', 'enum', 'extern', 'false', 'final', 'fn', 'for', 'if', 'impl', 'in', 'let', 'loop',
                       'macro', 'match', 'mod', 'move', 'mut', 'offsetof', 'override', 'priv', 'pub', 'pure', 'ref',
@@ -57,11 +72,14 @@ METHODS_RESOURCE = 'methods'
 
 ADD_PARAM_FN = 'param'
 ADD_SCOPE_FN = 'add_scope'
+FIELDS_FN = 'fields'
 ADD_PARAM_MEDIA_EXAMPLE = "." + ADD_PARAM_FN + '("alt", "media")'
 
 SPACES_PER_TAB = 4
 
 NESTED_TYPE_SUFFIX = 'item'
+ENUM_TYPE_SUFFIX = 'Enum'
+ENUM_UNKNOWN_VARIANT = 'Unknown'
 DELEGATE_TYPE = 'client::Delegate'
 REQUEST_PRIORITY = 100
 REQUEST_MARKER_TRAIT = 'client::RequestValue'
@@ -73,8 +91,12 @@ PART_MARKER_TRAIT = 'client::Part'
 NESTED_MARKER_TRAIT = 'client::NestedType'
 REQUEST_VALUE_PROPERTY_NAME = 'request'
 DELEGATE_PROPERTY_NAME = 'delegate'
+TIMEOUT_PROPERTY_NAME = 'timeout'
+MOCKABLE_FEATURE = 'mockable'
 TO_PARTS_MARKER = 'client::ToParts'
 UNUSED_TYPE_MARKER = 'client::UnusedType'
+PAGE_TOKEN_PARAM = 'pageToken'
+NEXT_PAGE_TOKEN_PROPERTY = 'nextPageToken'
 
 PROTOCOL_TYPE_INFO = {
     'simple' : {
@@ -83,7 +105,7 @@ PROTOCOL_TYPE_INFO = {
 If the upload fails for whichever reason, all progress is lost.""",
         'default': 'fs::File',
         'suffix': '',
-        'example_value': 'fs::File::open("file.ext").unwrap(), "application/octet-stream".parse().unwrap()'
+        'example_value': 'fs::File::open("file.ext").await.unwrap(), "application/octet-stream".parse().unwrap()'
     },
     'resumable' : {
         'arg_name': 'resumeable_stream',
@@ -94,10 +116,11 @@ certain amount of time as the server maintains state temporarily.
 The delegate will be asked for an `upload_url()`, and if not provided, will be asked to store an upload URL
 that was provided by the server, using `store_upload_url(...)`. The upload will be done in chunks, the delegate
 may specify the `chunk_size()` and may cancel the operation before each chunk is uploaded, using
-`cancel_chunk_upload(...)`.""",
+`cancel_chunk_upload(...)`. While the upload makes progress, its `UploadSession` is handed to
+`store_upload_session(...)`, which allows to continue it later using `upload_resumable_from_session(...)`.""",
         'default': 'fs::File',
         'suffix': '_resumable',
-        'example_value': 'fs::File::open("file.ext").unwrap(), "application/octet-stream".parse().unwrap()'
+        'example_value': 'fs::File::open("file.ext").await.unwrap(), "application/octet-stream".parse().unwrap()'
     }
 }
 
@@ -110,7 +133,7 @@ data_unit_multipliers = {
     '%': 1,
 }
 
-HUB_TYPE_PARAMETERS = ('C',)
+HUB_TYPE_PARAMETERS = ('S',)
 
 def items(p):
     if isinstance(p, dict):
@@ -333,6 +356,72 @@ def mangle_ident(n):
 def is_map_prop(p):
     return 'additionalProperties' in p
 
+# return True if the given property is a string with a fixed set of possible values
+def is_enum_property(p):
+    return 'enum' in p and p.get('type') == 'string'
+
+# Turn any string into an UpperCamelCase identifier, treating all non-alphanumeric characters as separators.
+# Words in all caps, like the ones in 'TYPE_UNSPECIFIED', are capitalized as well.
+def _to_camel_case(s):
+    words = [w for w in re.split('[^0-9A-Za-z]+', s) if w]
+    return ''.join(capitalize(w.isupper() and w.lower() or w) for w in words)
+
+# return the name of the enum representing the possible values of property pn of schema sn
+def enum_type_name(sn, pn):
+    return sn + _to_camel_case(pn) + ENUM_TYPE_SUFFIX
+
+# return [(variant_name, value, description), ...] for the given enum property, in order of declaration
+def enum_variants(p):
+    descriptions = p.get('enumDescriptions', [])
+    res = list()
+    seen = set([ENUM_UNKNOWN_VARIANT])
+    for vid, value in enumerate(p['enum']):
+        name = _to_camel_case(value) or 'Empty'
+        if name[0].isdigit():
+            name = 'Value' + name
+        if name in seen:
+            base = name
+            if name == ENUM_UNKNOWN_VARIANT:
+                base = name = name + 'Value'
+            count = 1
+            while name in seen:
+                count += 1
+                name = '%s%i' % (base, count)
+        seen.add(name)
+        desc = vid < len(descriptions) and descriptions[vid] or 'no description provided'
+        res.append((name, value, desc))
+    return res
+
+# yields (enum_type_name, property) for each enum the given schema uses directly, i.e. in its own properties
+# and their items or values.
+def schema_enums(s):
+    # The documentation of enums within arrays or maps may be found on the containing property
+    def enums_of(pn, p, outer=None):
+        if is_enum_property(p):
+            if outer is not None:
+                p = {'type': p['type'], 'enum': p['enum'],
+                     'enumDescriptions': p.get('enumDescriptions', outer.get('enumDescriptions', [])),
+                     'description': p.get('description', outer.get('description', 'no description provided'))}
+            yield enum_type_name(s['id'], pn), p
+        elif 'items' in p:
+            for e in enums_of(pn, p['items'], p):
+                yield e
+        elif is_map_prop(p):
+            for e in enums_of(pn, p['additionalProperties'], p):
+                yield e
+
+    if 'properties' in s:
+        for pn, p in items(s['properties']):
+            for e in enums_of(pn, p):
+                yield e
+    elif s.get('type') == 'array' and 'properties' in s.get('items', {}):
+        for pn, p in items(s['items']['properties']):
+            for e in enums_of(pn, p):
+                yield e
+    else:
+        for e in enums_of(NESTED_TYPE_SUFFIX, s):
+            yield e
+
 def _assure_unique_type_name(schemas, tn):
     if tn in schemas:
         tn += 'Nested'
@@ -343,8 +432,9 @@ def _assure_unique_type_name(schemas, tn):
 # sn = schema name
 # pn = property name
 # t = type dict
+# json_values = set of (sn, pn) whose 'any' typed values are json::Value, see operation_json_values()
 # NOTE: In case you don't understand how this algorithm really works ... me neither - THE AUTHOR
-def to_rust_type(schemas, sn, pn, t, allow_optionals=True, _is_recursive=False):
+def to_rust_type(schemas, sn, pn, t, allow_optionals=True, json_values=(), _is_recursive=False):
     def nested_type(nt):
         if 'items' in nt:
             nt = nt['items']
@@ -354,7 +444,7 @@ def to_rust_type(schemas, sn, pn, t, allow_optionals=True, _is_recursive=False):
             assert(is_nested_type_property(nt))
             # It's a nested type - we take it literally like $ref, but generate a name for the type ourselves
             return _assure_unique_type_name(schemas, nested_type_name(sn, pn))
-        return to_rust_type(schemas, sn, pn, nt, allow_optionals=False, _is_recursive=True)
+        return to_rust_type(schemas, sn, pn, nt, allow_optionals=False, json_values=json_values, _is_recursive=True)
 
     def wrap_type(tn):
         if allow_optionals:
@@ -381,6 +471,15 @@ def to_rust_type(schemas, sn, pn, t, allow_optionals=True, _is_recursive=False):
                 return wrap_type(nested_type(t))
         elif rust_type == USE_FORMAT:
             rust_type = TYPE_MAP[t['format']]
+        elif t['type'] == 'any' and (sn, pn) in json_values:
+            rust_type = 'json::Value'
+        elif t['type'] == 'string' and t.get('format') in STRING_FORMAT_TYPE_MAP and 'location' not in t:
+            rust_type = STRING_FORMAT_TYPE_MAP[t['format']]
+        elif is_enum_property(t):
+            if 'enum_type' in t:
+                rust_type = t['enum_type']
+            elif sn and pn:
+                rust_type = enum_type_name(sn, pn)
 
         if t.get('repeated', False):
             rust_type = 'Vec<%s>' % rust_type
@@ -392,6 +491,34 @@ def to_rust_type(schemas, sn, pn, t, allow_optionals=True, _is_recursive=False):
     except AttributeError as err:
         raise AssertionError("%s: unknown dict layout: %s" % (str(err), t))
 
+# Return the type to pass to client::serde::As for the given schema property, or None if the type
+# produced by to_rust_type() can be (de)serialized as is.
+# The result mirrors the structure of the rust type, e.g. Option<Vec<client::serde::Str>> for Option<Vec<i64>>.
+def serde_as_type(t, allow_optionals=True):
+    def wrap_type(tn):
+        if allow_optionals:
+            tn = "Option<%s>" % tn
+        return tn
+
+    if TREF in t or is_nested_type_property(t):
+        return None
+    if t.get('type') == 'array':
+        nt = serde_as_type(t['items'], allow_optionals=False)
+        return nt and wrap_type("Vec<%s>" % nt)
+    if t.get('type') == 'object':
+        if not is_map_prop(t):
+            return None
+        nt = serde_as_type(t['additionalProperties'], allow_optionals=False)
+        return nt and wrap_type("HashMap<String, %s>" % nt)
+    if 'location' in t or t.get('format') not in SERDE_AS_FORMAT_MAP:
+        return None
+    if t.get('type') == 'string' or TYPE_MAP.get(t.get('type')) == USE_FORMAT:
+        tn = SERDE_AS_FORMAT_MAP[t['format']]
+        if t.get('repeated', False):
+            return 'Vec<%s>' % tn
+        return wrap_type(tn)
+    return None
+
 # return True if this property is actually a nested type
 def is_nested_type_property(t):
     return 'type' in t and t['type'] == 'object' and 'properties' in t or ('items' in t and 'properties' in t['items'])
@@ -408,8 +535,8 @@ def activity_input_type(schemas, p):
     n = activity_rust_type(schemas, p, allow_optionals=False)
     if n == 'String':
         n = 'str'
-    # pods are copied anyway
-    elif is_pod_property(p) or p.get(TREF):
+    # pods are copied anyway, and enums are cheap to construct
+    elif is_pod_property(p) or p.get(TREF) or is_enum_property(p):
         return n
     return '&%s' % n
 
@@ -479,6 +606,27 @@ def schema_markers(s, c, transitive=True):
 
 ## -- End Rust TypeSystem -- @}
 
+# Return [(pn, fn_name, nested_fn_name, nested_type), ...] for each field of the given schema, used to generate its
+# client::Fields selector. nested_fn_name and nested_type are None unless the field holds a resource with fields
+# of its own, one or more of them, which can be selected as well.
+def field_selectors(schemas, s, c):
+    properties = s.get('properties') or s.get('items', dict()).get('properties', dict())
+    res = list()
+    for pn, p in items(properties):
+        fn_name = mangle_ident(pn)
+        # the methods of client::Fields itself can't be used
+        if fn_name == 'to_update_mask':
+            continue
+        nested_fn_name, nested_type = None, None
+        if not is_map_prop(p):
+            m = re.match(r'^(?:Vec<)?(?:Option<Box<)?(\w+)>*$', to_rust_type(schemas, s['id'], pn, p, allow_optionals=False))
+            ns = m and c.schemas.get(m.group(1))
+            if ns and ns.get('type') == 'object' and ns.get('properties'):
+                nested_fn_name, nested_type = mangle_ident(pn + 'With'), ns['id']
+        res.append((pn, fn_name, nested_fn_name, nested_type))
+    # end for each property
+    return res
+
 # NOTE: unfortunately, it turned out that sometimes fields are missing. The only way to handle this is to
 # use optionals everywhere. If that should ever change, we can make a decision here based on the
 # non-transitive markers that we get here !
@@ -528,6 +676,9 @@ def _method_params(m, required=None, location=None):
             continue
         np = deepcopy(p)
         np['name'] = pn
+        if is_enum_property(np):
+            _, resource, method = activity_split(m['id'])
+            np['enum_type'] = enum_type_name(mb_type(resource, method)[:-len('Call')], pn)
         try:
             # po = ['part', 'foo']
             # part_prio = 2 - 0 = 2
@@ -558,6 +709,133 @@ def method_request(c, m, marker=None):
 def method_response(c, m, marker=None):
     return _method_io('response', c, m, marker)
 
+# return the (name, property) of the repeated field holding the items of a paginated response schema, or None.
+# A property named 'items' is preferred, otherwise there must be exactly one array of objects.
+def pagination_item_property(response_schema):
+    candidates = list()
+    for pn, p in items(response_schema.get('properties', dict())):
+        if p.get('type') != 'array' or TREF not in p.get('items', dict()):
+            continue
+        if pn == 'items':
+            return pn, p
+        candidates.append((pn, p))
+    # end for each property
+    if len(candidates) == 1:
+        return candidates[0]
+    return None
+
+# return True if the method can be paged through by feeding the response's 'nextPageToken'
+# back into the 'pageToken' parameter
+def is_paginated_method(m, response_schema):
+    if response_schema is None:
+        return False
+    page_token = m.get('parameters', dict()).get(PAGE_TOKEN_PARAM)
+    if page_token is None or page_token.get('required', False):
+        return False
+    return NEXT_PAGE_TOKEN_PROPERTY in response_schema.get('properties', dict())
+
+# Describes how a long-running operation schema is polled until it is done.
+# schema: the schema id; done: rust expression telling whether `self` is done; source: the field holding the
+# resource name or url the get methods' parameters are taken from; http_status: the field with the HTTP status code
+# of a failed operation, or None; error: whether it has an 'error' field; results: the names of its `Any` typed
+# map fields, like 'response' and 'metadata'; getters: [(resource, method, [(param_name, collection, pattern), ...]), ...] in order of preference, where
+# collection is None for parameters receiving the whole resource name, which must match pattern if set.
+OperationPoller = collections.namedtuple('OperationPoller',
+                                         ['schema', 'done', 'source', 'http_status', 'error', 'results', 'getters'])
+
+# return an OperationPoller for each schema which represents a long-running operation, i.e. which has a name, a
+# 'done' flag or a 'status', and can be obtained again using a GET method of the API.
+def operation_pollers(c):
+    res = list()
+    for sid, s in sorted(c.schemas.items()):
+        props = s.get('properties', dict())
+        if 'name' not in props:
+            continue
+        status = props.get('status', dict())
+        if props.get('done', dict()).get('type') == 'boolean':
+            done = 'self.done.unwrap_or(false)'
+        elif is_enum_property(status) and 'DONE' in status['enum']:
+            variant = [n for n, v, _ in enum_variants(status) if v == 'DONE'][0]
+            done = 'self.status == Some(%s::%s)' % (enum_type_name(sid, 'status'), variant)
+        elif status.get('type') == 'string' and 'enum' not in status:
+            done = 'self.status.as_deref() == Some("DONE")'
+        else:
+            continue
+        source = 'selfLink' in props and 'selfLink' or 'name'
+
+        getters = list()
+        for fqan, m in sorted(c.fqan_map.items()):
+            if m.get('httpMethod') != 'GET' or m.get('response', dict()).get(TREF) != sid:
+                continue
+            # the methods returning a single operation are 'get', and sometimes 'getOperation'
+            if not m['id'].split('.')[-1].lower().startswith('get'):
+                continue
+            required = [pn for pn in m.get('parameterOrder', list())
+                        if m['parameters'][pn].get('required', False)]
+            args = list()
+            for pn in required:
+                p = m['parameters'][pn]
+                if p.get('type') != 'string' or is_enum_property(p):
+                    break
+                if '{+%s}' % pn in m['path']:
+                    # a self-link isn't the name of the resource
+                    if source != 'name':
+                        break
+                    args.append((pn, None, p.get('pattern')))
+                    continue
+                # the collection is the literal segment before the parameter, like 'zones' in 'zones/{zone}'
+                cm = re.search(r'(?:^|/)([A-Za-z]\w*)/\{%s\}' % pn, m['path'])
+                args.append((pn, cm and cm.group(1) or pn + 's', None))
+            else:
+                if args:
+                    _, resource, method = activity_split(fqan)
+                    getters.append((resource, method, args))
+            # end for each required parameter
+        # end for each method
+        if not getters:
+            continue
+        # prefer the most specific getters, like those for zonal operations over the one for global ones
+        getters.sort(key=lambda g: (-len(g[2]), -len((g[2][0][2] or '').split('/'))))
+
+        http_status = 'httpErrorStatusCode' in props and 'httpErrorStatusCode' or None
+        results = [pn for pn in ('response', 'metadata') if is_map_prop(props.get(pn, dict()))]
+        res.append(OperationPoller(sid, done, source, http_status, 'error' in props, results, getters))
+    # end for each schema
+    return res
+
+# return the set of (schema_id, property_name) of the result fields of operations, and of the details of their errors,
+# whose 'any' typed values are json::Value, as they are decoded into other types by the generated helpers.
+# Other 'any' typed fields stay as they are.
+def operation_json_values(c):
+    res = set()
+    for op in operation_pollers(c):
+        props = c.schemas[op.schema]['properties']
+        res.update((op.schema, pn) for pn in op.results)
+        error = props.get('error', dict()).get(TREF)
+        if 'details' in c.schemas.get(error, dict()).get('properties', dict()):
+            res.add((error, 'details'))
+    return res
+
+# return {'response'|'metadata': schema_id} of the types the result fields of the operation the given method starts
+# decode into, as configured for its id in 'results', like api.operation_results. Fields without a configured type
+# hold untyped json.
+def operation_result_types(c, m, results):
+    res = dict((results or dict()).get(m['id'], dict()))
+    for field, tn in res.items():
+        assert field in ('response', 'metadata'), "%s: unknown operation result field '%s'" % (m['id'], field)
+        assert tn in c.schemas, "%s: unknown operation result type '%s'" % (m['id'], tn)
+    return res
+
+# return the ids of all schemas operation results decode into, along with the ones they use. They have to be
+# generated even though no method uses them directly.
+def operation_result_schemas(c, results):
+    named = set()
+    for m in c.fqan_map.values():
+        named.update(operation_result_types(c, m, results).values())
+    if not named:
+        return named
+    return set(sid for sid, s in c.schemas.items() if named.intersection(_traverse_schema_ids(s, c)))
+
 # return string like 'n.clone()', but depending on the type name of tn (e.g. &str -> n.to_string())
 def rust_copy_value_s(n, tn, p):
     if 'clone_value' in p:
@@ -565,7 +843,7 @@ def rust_copy_value_s(n, tn, p):
     nc = n + '.clone()'
     if tn == '&str':
         nc = n + '.to_string()'
-    elif is_pod_property(p) or p.get(TREF):
+    elif is_pod_property(p) or p.get(TREF) or is_enum_property(p):
         nc = n
     return nc
 
@@ -655,8 +933,25 @@ while executing the actual API request.
 
 It should be used to handle progress information, and to implement a certain level of resilience."""})
     params.append(dp)
+    tp = type(m)({ 'name': call_timeout_name(m),
+           TREF: 'std::time::Duration',
+           'skip_example' : True,
+           'priority': 0,
+           'is_query_param': False,
+           'description':
+"""The time after which an attempt to make the request is abandoned, failing with `Error::Timeout` unless the
+delegate decides to retry. It includes reading the response, unless its media is downloaded.
+
+It defaults to the timeout of the hub, if any."""})
+    params.append(tp)
     return params, request_value
 
+# Returns the name of the property holding the timeout of a call, which can't clash with a parameter of the method
+def call_timeout_name(m):
+    if TIMEOUT_PROPERTY_NAME in m.get('parameters', dict()):
+        return 'call_' + TIMEOUT_PROPERTY_NAME
+    return TIMEOUT_PROPERTY_NAME
+
 
 ## -- End Activity Utilities -- @}
 
@@ -899,7 +1194,7 @@ def hub_type_params_s():
 
 # return a list of where statements to server as bounds for the hub.
 def hub_type_bounds():
-    return ['C: BorrowMut<hyper::Client<hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>, hyper::body::Body>>']
+    return ["S: client::Transport"]
 
 # Returns True if this API has particular authentication scopes to choose from
 def supports_scopes(auth):
@@ -947,6 +1242,20 @@ def mb_additional_type_params(m):
 def mb_type(r, m):
     return "%s%sCall" % (singular(canonical_type_name(r)), dot_sep_to_canonical_type_name(m))
 
+# return the name of the trait making the methods of the given resource mockable
+def resource_trait_type(r):
+    return "%sApi" % canonical_type_name(r)
+
+# return the name of the struct holding the parameters of a method, as taken by the method of the resource trait
+def mock_params_type(r, m):
+    return "%s%sParams" % (singular(canonical_type_name(r)), dot_sep_to_canonical_type_name(m))
+
+# return the names of the methods of the given resource which are part of its trait, i.e. which don't upload media,
+# or can be called without doing so as their call builder type starts with no_upload_prefix
+def mockable_methods(c, r, no_upload_prefix=None):
+    return [a for a in c.rta_map[r] if not method_media_params(c.fqan_map[to_fqan(c.rtc_map[r], r, a)])
+                                       or (no_upload_prefix and mb_type(r, a).startswith(no_upload_prefix))]
+
 # canonicalName = util.canonical_name()
 def hub_type(schemas, canonicalName):
     name = canonical_type_name(canonicalName)
@@ -4,7 +4,10 @@ import unittest
 import json
 import importlib_resources
 
-from .util import to_api_version, library_name, re_find_replacements, to_rust_type, new_context
+from .util import (to_api_version, library_name, re_find_replacements, to_rust_type, new_context,
+                   pagination_item_property, is_paginated_method, enum_variants, schema_enums, serde_as_type,
+                   field_selectors, Context, resource_trait_type, mock_params_type, operation_pollers,
+                   operation_result_types, operation_json_values)
 from . import test_data
 
 
@@ -87,7 +90,7 @@ class UtilsTest(unittest.TestCase):
         test_properties = (
             ('Album', 'title', 'String'), # string
             ('Status', 'code', 'i32'), # numeric
-            ('Album', 'mediaItemsCount', 'String'), # numeric via "count" keyword
+            ('Album', 'mediaItemsCount', 'i64'), # int64, transmitted as string
             ('Album', 'isWriteable', 'bool'), # boolean
             ('Album', 'shareInfo', 'ShareInfo'), # reference type
             ('SearchMediaItemsResponse', 'mediaItems', 'Vec<MediaItem>'), # array
@@ -111,6 +114,162 @@ class UtilsTest(unittest.TestCase):
         rust_type = to_rust_type(schemas, class_name, property_name, property_value, allow_optionals=True)
         self.assertEqual(rust_type, 'Option<Vec<HashMap<String, String>>>')
 
+    def test_string_formats(self):
+        full_api_schema = read_test_json_file('photoslibrary-api.json')
+        schemas = full_api_schema['schemas']
+
+        media_items_count = schemas['Album']['properties']['mediaItemsCount']
+        self.assertEqual(serde_as_type(media_items_count), 'Option<client::serde::Str>')
+        self.assertEqual(serde_as_type(media_items_count, allow_optionals=False), 'client::serde::Str')
+        self.assertIsNone(serde_as_type(schemas['Album']['properties']['title']))
+        self.assertIsNone(serde_as_type(schemas['Album']['properties']['shareInfo']))
+
+        creation_time = schemas['MediaMetadata']['properties']['creationTime']
+        self.assertEqual(to_rust_type(schemas, 'MediaMetadata', 'creationTime', creation_time),
+                         'Option<client::chrono::DateTime<client::chrono::offset::Utc>>')
+        self.assertIsNone(serde_as_type(creation_time))
+
+        for p, rust_type, serde_as in (
+            ({'type': 'array', 'items': {'type': 'string', 'format': 'byte'}},
+             'Option<Vec<Vec<u8>>>', 'Option<Vec<client::serde::Base64>>'),
+            ({'type': 'object', 'additionalProperties': {'type': 'string', 'format': 'uint64'}},
+             'Option<HashMap<String, u64>>', 'Option<HashMap<String, client::serde::Str>>'),
+            ({'type': 'string', 'format': 'google-duration'},
+             'Option<client::chrono::Duration>', 'Option<client::serde::Duration>'),
+        ):
+            self.assertEqual(to_rust_type(schemas, 'Foo', 'bar', p), rust_type)
+            self.assertEqual(serde_as_type(p), serde_as)
+
+        # parameters are passed as strings, and keep their string type
+        param = {'type': 'string', 'format': 'int64', 'location': 'query'}
+        self.assertEqual(to_rust_type(schemas, None, 'pageSize', param), 'Option<String>')
+        self.assertIsNone(serde_as_type(param))
+
+    def test_pagination(self):
+        full_api_schema = read_test_json_file('photoslibrary-api.json')
+        schemas = full_api_schema['schemas']
+        resources = full_api_schema['resources']
+
+        list_albums = resources['albums']['methods']['list']
+        response = schemas['ListAlbumsResponse']
+        self.assertTrue(is_paginated_method(list_albums, response))
+        self.assertEqual(pagination_item_property(response)[0], 'albums')
+
+        # page token is part of the request body, not a parameter
+        search = resources['mediaItems']['methods']['search']
+        self.assertFalse(is_paginated_method(search, schemas['SearchMediaItemsResponse']))
+
+        get_album = resources['albums']['methods']['get']
+        self.assertFalse(is_paginated_method(get_album, schemas['Album']))
+        self.assertIsNone(pagination_item_property(schemas['Album']))
+
+    def test_enums(self):
+        full_api_schema = read_test_json_file('photoslibrary-api.json')
+        schemas = full_api_schema['schemas']
+
+        media_type_filter = schemas['MediaTypeFilter']
+        media_types = media_type_filter['properties']['mediaTypes']
+        rust_type = to_rust_type(schemas, 'MediaTypeFilter', 'mediaTypes', media_types, allow_optionals=True)
+        self.assertEqual(rust_type, 'Option<Vec<MediaTypeFilterMediaTypesEnum>>')
+
+        enums = list(schema_enums(media_type_filter))
+        self.assertEqual(len(enums), 1)
+        enum_name, enum_prop = enums[0]
+        self.assertEqual(enum_name, 'MediaTypeFilterMediaTypesEnum')
+        # descriptions are taken from the array property if its items don't have any
+        self.assertEqual([(n, v) for n, v, _ in enum_variants(enum_prop)],
+                         [('AllMedia', 'ALL_MEDIA'), ('Video', 'VIDEO'), ('Photo', 'PHOTO')])
+        self.assertTrue(enum_variants(enum_prop)[1][2].startswith('All media items that are considered videos.'))
+
+        # values which make bad identifiers still result in unique variant names
+        variants = enum_variants({'type': 'string', 'enum': ['UNKNOWN', 'unknown', '1080p', '', 'a-b', 'A_B']})
+        self.assertEqual([n for n, _, _ in variants],
+                         ['UnknownValue', 'UnknownValue2', 'Value1080p', 'Empty', 'AB', 'AB2'])
+
+    def test_field_selectors(self):
+        full_api_schema = read_test_json_file('photoslibrary-api.json')
+        schemas = full_api_schema['schemas']
+        c = Context(dict(), dict(), dict(), dict(), schemas)
+
+        selectors = field_selectors(schemas, schemas['ListAlbumsResponse'], c)
+        self.assertEqual(sorted(selectors), [('albums', 'albums', 'albums_with', 'Album'),
+                                             ('nextPageToken', 'next_page_token', None, None)])
+
+        selectors = dict((pn, s) for pn, *s in field_selectors(schemas, schemas['Album'], c))
+        self.assertEqual(selectors['shareInfo'], ['share_info', 'share_info_with', 'ShareInfo'])
+        self.assertEqual(selectors['title'], ['title', None, None])
+
+    def test_mockable_names(self):
+        self.assertEqual(resource_trait_type('mediaItems'), 'MediaItemsApi')
+        self.assertEqual(mock_params_type('mediaItems', 'batchCreate'), 'MediaItemBatchCreateParams')
+        self.assertEqual(mock_params_type('projects', 'locations.instances.get'), 'ProjectLocationInstanceGetParams')
+
+    def test_operation_pollers(self):
+        operation = {'id': 'Operation', 'type': 'object', 'properties': {
+            'name': {'type': 'string'}, 'done': {'type': 'boolean'}, 'error': {'$ref': 'Status'},
+            'response': {'type': 'object', 'additionalProperties': {'type': 'any'}}}}
+        zonal = {'id': 'Operation', 'type': 'object', 'properties': {
+            'name': {'type': 'string'}, 'selfLink': {'type': 'string'}, 'httpErrorStatusCode': {'type': 'integer'},
+            'status': {'type': 'string', 'enum': ['PENDING', 'DONE']}}}
+        get = {'id': 'api.projects.operations.get', 'httpMethod': 'GET', 'path': 'v1/{+name}',
+               'parameterOrder': ['name'], 'response': {'$ref': 'Operation'},
+               'parameters': {'name': {'type': 'string', 'required': True, 'location': 'path',
+                                       'pattern': '^projects/[^/]+/operations/[^/]+$'}}}
+        get_zonal = {'id': 'api.zoneOperations.get', 'httpMethod': 'GET', 'response': {'$ref': 'Operation'},
+                     'path': '{project}/zones/{zone}/operations/{operation}',
+                     'parameterOrder': ['project', 'zone', 'operation'],
+                     'parameters': dict((pn, {'type': 'string', 'required': True, 'location': 'path'})
+                                        for pn in ('project', 'zone', 'operation'))}
+        get_global = {'id': 'api.globalOperations.get', 'httpMethod': 'GET', 'response': {'$ref': 'Operation'},
+                      'path': 'projects/{project}/global/operations/{operation}',
+                      'parameterOrder': ['project', 'operation'],
+                      'parameters': dict((pn, {'type': 'string', 'required': True, 'location': 'path'})
+                                         for pn in ('project', 'operation'))}
+        recognize = {'id': 'api.speech.recognize', 'httpMethod': 'POST', 'response': {'$ref': 'Operation'}}
+
+        c = Context(dict(), {'api.projects.operations.get': get, 'api.speech.recognize': recognize},
+                    dict(), dict(), {'Operation': operation, 'RecognizeResponse': {'id': 'RecognizeResponse'}})
+        op, = operation_pollers(c)
+        self.assertEqual(op.done, 'self.done.unwrap_or(false)')
+        self.assertEqual((op.source, op.http_status, op.error, op.results), ('name', None, True, ['response']))
+        self.assertEqual(op.getters, [('projects', 'operations.get',
+                                       [('name', None, '^projects/[^/]+/operations/[^/]+$')])])
+        results = {'api.speech.recognize': {'response': 'RecognizeResponse'}}
+        self.assertEqual(operation_result_types(c, recognize, results), {'response': 'RecognizeResponse'})
+        self.assertEqual(operation_result_types(c, get, results), {})
+        self.assertEqual(operation_result_types(c, recognize, None), {})
+        self.assertRaises(AssertionError, operation_result_types, c, recognize,
+                          {'api.speech.recognize': {'response': 'Unknown'}})
+
+        # only the results of operations and the details of their errors hold arbitrary json
+        status = {'id': 'Status', 'type': 'object', 'properties': {'details': {
+            'type': 'array', 'items': {'type': 'object', 'additionalProperties': {'type': 'any'}}}}}
+        other = {'id': 'Other', 'type': 'object', 'properties': {
+            'labels': {'type': 'object', 'additionalProperties': {'type': 'any'}}}}
+        schemas = {'Operation': operation, 'Status': status, 'Other': other}
+        c = Context(dict(), {'api.projects.operations.get': get}, dict(), dict(), schemas)
+        json_values = operation_json_values(c)
+        self.assertEqual(json_values, {('Operation', 'response'), ('Status', 'details')})
+        rust_type = lambda sn, pn: to_rust_type(schemas, sn, pn, schemas[sn]['properties'][pn], json_values=json_values)
+        self.assertEqual(rust_type('Operation', 'response'), 'Option<HashMap<String, json::Value>>')
+        self.assertEqual(rust_type('Status', 'details'), 'Option<Vec<HashMap<String, json::Value>>>')
+        self.assertEqual(rust_type('Other', 'labels'), 'Option<HashMap<String, String>>')
+
+        # the most specific getter comes first, and parameters are taken from the self-link
+        c = Context(dict(), {'api.globalOperations.get': get_global, 'api.zoneOperations.get': get_zonal,
+                             'api.projects.operations.get': get}, dict(), dict(), {'Operation': zonal})
+        op, = operation_pollers(c)
+        self.assertEqual(op.done, 'self.status == Some(OperationStatusEnum::Done)')
+        self.assertEqual((op.source, op.http_status, op.error, op.results), ('selfLink', 'httpErrorStatusCode', False, []))
+        self.assertEqual(op.getters, [
+            ('zoneOperations', 'get', [('project', 'projects', None), ('zone', 'zones', None),
+                                       ('operation', 'operations', None)]),
+            ('globalOperations', 'get', [('project', 'projects', None), ('operation', 'operations', None)])])
+
+        # without a method to get it, it's not an operation to poll
+        c = Context(dict(), {'api.speech.recognize': recognize}, dict(), dict(), {'Operation': operation})
+        self.assertEqual(operation_pollers(c), [])
+
 
 def main():
     unittest.main()
@@ -1,23 +1,35 @@
 use std;
 use std::error;
 use std::fmt::{self, Display};
-use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::future::Future;
+use std::io::{self, Cursor, SeekFrom, Write};
+use std::pin::Pin;
 use std::str::FromStr;
-use std::thread::sleep;
-use std::time::Duration;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
 use itertools::Itertools;
 
 use hyper;
-use hyper::header::{HeaderMap, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_TYPE, RETRY_AFTER, USER_AGENT,
+};
 use hyper::Method;
 use hyper::StatusCode;
 
 use mime::{Attr, Mime, SubLevel, TopLevel, Value};
 use oauth2;
+use rand::Rng;
 
 use serde_json as json;
 
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, ReadBuf};
+
+use tracing::Instrument;
+
+pub use chrono;
+
 const LINE_ENDING: &'static str = "\r\n";
 
 pub enum Retry {
@@ -60,15 +72,276 @@ pub trait Part {}
 /// They have no special meaning, this trait just marks them for completeness.
 pub trait NestedType {}
 
+/// The error a `GetToken` implementation fails with.
+pub type GetTokenError = Box<dyn error::Error + Send + Sync>;
+
+/// The future returned by `GetToken::get_token()`.
+pub type GetTokenFuture<'a> =
+    Pin<Box<dyn Future<Output = std::result::Result<Option<String>, GetTokenError>> + Send + 'a>>;
+
+/// A source of credentials for the requests made by a hub.
+///
+/// It is implemented for the `yup-oauth2` authenticator, but any other token source, like a sidecar or
+/// a metadata server, can be plugged in by implementing it. `String` serves as static bearer token,
+/// `ApiKey` authorizes requests with an API key only, and `NoToken` sends no credentials at all.
+pub trait GetToken: Send + Sync {
+    /// Obtain an OAuth2 access token valid for the given scopes, which will be sent as bearer token.
+    /// `None` indicates that requests should be made without an `Authorization` header.
+    fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a>;
+
+    /// The API key to send along with each request, if any.
+    /// It is required by methods which don't need authorization by the user.
+    fn api_key(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T: GetToken + ?Sized> GetToken for Box<T> {
+    fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a> {
+        (**self).get_token(scopes)
+    }
+
+    fn api_key(&self) -> Option<String> {
+        (**self).api_key()
+    }
+}
+
+impl<S> GetToken for oauth2::authenticator::Authenticator<S>
+where
+    S: hyper::service::Service<hyper::Uri> + Clone + Send + Sync + 'static,
+    S::Response: hyper::client::connect::Connection + AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    S::Future: Send + Unpin + 'static,
+    S::Error: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a> {
+        Box::pin(async move {
+            self.token(scopes)
+                .await
+                .map(|token| Some(token.as_str().to_string()))
+                .map_err(|err| err.into())
+        })
+    }
+}
+
+/// A static bearer token, which is sent as is, no matter which scopes are required.
+impl GetToken for String {
+    fn get_token<'a>(&'a self, _scopes: &'a [&str]) -> GetTokenFuture<'a> {
+        Box::pin(futures::future::ready(Ok(Some(self.clone()))))
+    }
+}
+
+/// Authorizes requests with the contained API key only, without sending any access token.
+#[derive(Clone, Debug)]
+pub struct ApiKey(pub String);
+
+impl GetToken for ApiKey {
+    fn get_token<'a>(&'a self, _scopes: &'a [&str]) -> GetTokenFuture<'a> {
+        Box::pin(futures::future::ready(Ok(None)))
+    }
+
+    fn api_key(&self) -> Option<String> {
+        Some(self.0.clone())
+    }
+}
+
+/// Sends requests without any credentials, e.g. to access public data or a local emulator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoToken;
+
+impl GetToken for NoToken {
+    fn get_token<'a>(&'a self, _scopes: &'a [&str]) -> GetTokenFuture<'a> {
+        Box::pin(futures::future::ready(Ok(None)))
+    }
+}
+
+/// The error a `Transport` fails with.
+pub type TransportError = Box<dyn error::Error + Send + Sync>;
+
+/// The future returned by `Transport::send()`.
+pub type TransportFuture = Pin<
+    Box<
+        dyn Future<Output = std::result::Result<hyper::Response<hyper::body::Body>, TransportError>>
+            + Send,
+    >,
+>;
+
+/// The service a hub sends its requests through.
+///
+/// It is implemented for `hyper::Client`, and for any other `tower` service taking and returning
+/// hyper requests and responses. That way, middleware like rate limits, concurrency limits or metrics
+/// can be layered under every hub.
+///
+/// The service is cloned for each request, so services keeping state which must be shared
+/// between requests, like a rate limit, should be wrapped into a `tower::buffer::Buffer`.
+pub trait Transport: Clone + Send + Sync + 'static {
+    /// Send the given request once the service is ready to take it.
+    fn send(&self, req: hyper::Request<hyper::body::Body>) -> TransportFuture;
+}
+
+impl<S> Transport for S
+where
+    S: hyper::service::Service<
+            hyper::Request<hyper::body::Body>,
+            Response = hyper::Response<hyper::body::Body>,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Error: Into<TransportError>,
+    S::Future: Send + 'static,
+{
+    fn send(&self, req: hyper::Request<hyper::body::Body>) -> TransportFuture {
+        let mut service = self.clone();
+        Box::pin(async move {
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(Into::into)?;
+            service.call(req).await.map_err(Into::into)
+        })
+    }
+}
+
 /// A utility to specify reader types which provide seeking capabilities too
-pub trait ReadSeek: Seek + Read {}
-impl<T: Seek + Read> ReadSeek for T {}
+pub trait ReadSeek: AsyncRead + AsyncSeek + Unpin + Send {}
+impl<T: AsyncRead + AsyncSeek + Unpin + Send> ReadSeek for T {}
 
 /// A trait for all types that can convert themselves into a *parts* string
 pub trait ToParts {
     fn to_parts(&self) -> String;
 }
 
+/// A selection of the fields of a resource, possibly with selections of the fields of nested resources.
+///
+/// Displayed, it uses the syntax of the `fields` parameter to request a partial response, like
+/// `id,name,permissions(id,role)`. It can also be turned into a field mask, as used by the `updateMask`
+/// parameter of methods patching a resource.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldSelector {
+    fields: Vec<(String, Option<FieldSelector>)>,
+}
+
+impl FieldSelector {
+    /// Select the given field as a whole.
+    pub fn field<T: Into<String>>(self, name: T) -> FieldSelector {
+        self.select(name.into(), None)
+    }
+
+    /// Select the given fields of the resource held by field `name`.
+    pub fn nested<T: Into<String>>(self, name: T, fields: FieldSelector) -> FieldSelector {
+        self.select(name.into(), Some(fields))
+    }
+
+    /// Returns true if no field was selected.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the selected fields as field mask, i.e. a comma separated list of paths like `name,owner.email`.
+    pub fn to_update_mask(&self) -> String {
+        self.paths("").join(",")
+    }
+
+    fn select(mut self, name: String, fields: Option<FieldSelector>) -> FieldSelector {
+        // An empty nested selection is the same as selecting the field as a whole.
+        let fields = fields.filter(|f| !f.is_empty());
+        match self.fields.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => {
+                entry.1 = match (entry.1.take(), fields) {
+                    (Some(selected), Some(fields)) => Some(
+                        fields
+                            .fields
+                            .into_iter()
+                            .fold(selected, |s, (name, fields)| s.select(name, fields)),
+                    ),
+                    // Once selected as a whole, narrowing it down doesn't change anything.
+                    _ => None,
+                };
+            }
+            None => self.fields.push((name, fields)),
+        }
+        self
+    }
+
+    fn paths(&self, prefix: &str) -> Vec<String> {
+        let mut paths = Vec::new();
+        for (name, fields) in self.fields.iter() {
+            let path = format!("{}{}", prefix, name);
+            match *fields {
+                Some(ref fields) => paths.extend(fields.paths(&format!("{}.", path))),
+                None => paths.push(path),
+            }
+        }
+        paths
+    }
+}
+
+impl Display for FieldSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, fields)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(name)?;
+            if let Some(ref fields) = *fields {
+                write!(f, "({})", fields)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `FieldSelector` for the fields of resource `T`.
+///
+/// It is obtained using the `fields()` function of the resource, which provides a method to select each of
+/// its fields, like `File::fields().id().name()`.
+pub struct Fields<T> {
+    pub(crate) selector: FieldSelector,
+    resource: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> Fields<T> {
+    /// Returns the selected fields as field mask, see `FieldSelector::to_update_mask()`.
+    pub fn to_update_mask(&self) -> String {
+        self.selector.to_update_mask()
+    }
+}
+
+impl<T> Default for Fields<T> {
+    fn default() -> Fields<T> {
+        Fields {
+            selector: FieldSelector::default(),
+            resource: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Fields<T> {
+    fn clone(&self) -> Fields<T> {
+        Fields {
+            selector: self.selector.clone(),
+            resource: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Fields<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.selector, f)
+    }
+}
+
+impl<T> Display for Fields<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.selector, f)
+    }
+}
+
+impl<T> From<Fields<T>> for FieldSelector {
+    fn from(fields: Fields<T>) -> FieldSelector {
+        fields.selector
+    }
+}
+
 /// A utility type which can decode a server response that indicates error
 #[derive(Deserialize)]
 pub struct JsonServerError {
@@ -80,32 +353,366 @@ pub struct JsonServerError {
 /// The latter happen if the sent parameters or request structures are unsound
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ErrorResponse {
-    pub error: ServerError,
+    pub error: ApiError,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct ServerError {
-    pub errors: Vec<ServerMessage>,
+/// An error returned by the server, modelled after
+/// [`google.rpc.Status`](https://cloud.google.com/apis/design/errors#error_model).
+///
+/// Older APIs describe the error using `errors`, whereas newer ones use `status` and `details`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ApiError {
+    /// The HTTP status code.
     pub code: u16,
+    /// The canonical error code, like `PERMISSION_DENIED`.
+    pub status: Option<String>,
+    #[serde(default)]
     pub message: String,
+    #[serde(default)]
+    pub errors: Vec<ServerMessage>,
+    #[serde(default)]
+    pub details: Vec<ErrorDetail>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// The name `ApiError` used to have, kept for compatibility.
+pub type ServerError = ApiError;
+
+/// The canonical error codes of `google.rpc.Code`, indexed by their number, along with the HTTP status
+/// code each one maps to.
+const CANONICAL_CODES: [(&'static str, u16); 17] = [
+    ("OK", 200),
+    ("CANCELLED", 499),
+    ("UNKNOWN", 500),
+    ("INVALID_ARGUMENT", 400),
+    ("DEADLINE_EXCEEDED", 504),
+    ("NOT_FOUND", 404),
+    ("ALREADY_EXISTS", 409),
+    ("PERMISSION_DENIED", 403),
+    ("RESOURCE_EXHAUSTED", 429),
+    ("FAILED_PRECONDITION", 400),
+    ("ABORTED", 409),
+    ("OUT_OF_RANGE", 400),
+    ("UNIMPLEMENTED", 501),
+    ("INTERNAL", 500),
+    ("UNAVAILABLE", 503),
+    ("DATA_LOSS", 500),
+    ("UNAUTHENTICATED", 401),
+];
+
+/// Reasons of a `ServerMessage` or `ErrorInfo` which indicate that a rate limit was hit temporarily.
+const RATE_LIMIT_REASONS: [&'static str; 3] = [
+    "rateLimitExceeded",
+    "userRateLimitExceeded",
+    "RATE_LIMIT_EXCEEDED",
+];
+
+impl ApiError {
+    /// Decode the error from the body of a failed response, which is usually wrapped into an object
+    /// with a single `error` field.
+    pub fn from_body(body: &[u8]) -> Option<ApiError> {
+        json::from_slice::<ErrorResponse>(body)
+            .map(|r| r.error)
+            .or_else(|_| json::from_slice::<ApiError>(body))
+            .ok()
+    }
+
+    /// Decode the `error` of a long-running operation, which is a `google.rpc.Status` with a numeric
+    /// canonical code, or, for older APIs, an object with a list of `errors`. `http_status` is the HTTP
+    /// status code some operations report separately.
+    ///
+    /// Returns `None` if the value doesn't describe an error.
+    pub fn from_operation_error(error: &json::Value, http_status: Option<u16>) -> Option<ApiError> {
+        let string = |v: &json::Value, field: &str| {
+            v.get(field)
+                .and_then(json::Value::as_str)
+                .map(String::from)
+        };
+        let errors: Vec<ServerMessage> = error
+            .get("errors")
+            .and_then(json::Value::as_array)
+            .map(|errors| {
+                errors
+                    .iter()
+                    .map(|e| ServerMessage {
+                        domain: String::new(),
+                        reason: string(e, "code").unwrap_or_default(),
+                        message: string(e, "message").unwrap_or_default(),
+                        location_type: None,
+                        location: string(e, "location"),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let canonical = error
+            .get("code")
+            .and_then(json::Value::as_u64)
+            .and_then(|code| CANONICAL_CODES.get(code as usize));
+        if canonical.map_or(true, |&(status, _)| status == "OK") && errors.is_empty() {
+            return None;
+        }
+
+        Some(ApiError {
+            code: http_status.or(canonical.map(|&(_, code)| code)).unwrap_or(500),
+            status: canonical.map(|&(status, _)| status.to_string()),
+            message: string(error, "message")
+                .or_else(|| errors.first().map(|e| e.message.clone()))
+                .unwrap_or_default(),
+            errors,
+            details: error
+                .get("details")
+                .and_then(|d| json::from_value(d.clone()).ok())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Returns true if the canonical error code is the given one, or, if there is none, the HTTP
+    /// status code is the one it maps to.
+    fn has_status(&self, status: &str, code: StatusCode) -> bool {
+        match self.status {
+            Some(ref s) => s == status,
+            None => self.code == code.as_u16(),
+        }
+    }
+
+    /// Returns true if the requested resource doesn't exist.
+    pub fn is_not_found(&self) -> bool {
+        self.has_status("NOT_FOUND", StatusCode::NOT_FOUND)
+    }
+
+    /// Returns true if the resource to be created exists already.
+    pub fn is_already_exists(&self) -> bool {
+        self.has_status("ALREADY_EXISTS", StatusCode::CONFLICT)
+    }
+
+    /// Returns true if the credentials lack the permission for the request.
+    pub fn is_permission_denied(&self) -> bool {
+        self.has_status("PERMISSION_DENIED", StatusCode::FORBIDDEN) && !self.is_rate_limited()
+    }
+
+    /// Returns true if the request lacked valid credentials.
+    pub fn is_unauthenticated(&self) -> bool {
+        self.has_status("UNAUTHENTICATED", StatusCode::UNAUTHORIZED)
+    }
+
+    /// Returns true if a rate limit was exceeded. Older APIs signal this using status 403.
+    pub fn is_rate_limited(&self) -> bool {
+        self.has_status("RESOURCE_EXHAUSTED", StatusCode::TOO_MANY_REQUESTS)
+            || self
+                .errors
+                .iter()
+                .any(|m| RATE_LIMIT_REASONS.contains(&m.reason.as_str()))
+            || self
+                .error_info()
+                .map_or(false, |i| RATE_LIMIT_REASONS.contains(&i.reason.as_str()))
+    }
+
+    /// The delay the server asks to wait before retrying, if any.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.details.iter().find_map(|d| match d {
+            ErrorDetail::RetryInfo(RetryInfo {
+                retry_delay: Some(delay),
+            }) => delay.to_std().ok(),
+            _ => None,
+        })
+    }
+
+    /// The structured cause of the error, if any.
+    pub fn error_info(&self) -> Option<&ErrorInfo> {
+        self.details.iter().find_map(|d| match d {
+            ErrorDetail::ErrorInfo(info) => Some(info),
+            _ => None,
+        })
+    }
+
+    /// The invalid fields of the request, if any.
+    pub fn field_violations(&self) -> impl Iterator<Item = &FieldViolation> {
+        self.details
+            .iter()
+            .filter_map(|d| match d {
+                ErrorDetail::BadRequest(b) => Some(&b.field_violations),
+                _ => None,
+            })
+            .flatten()
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(ref status) => writeln!(f, "{} ({}): {}", status, self.code, self.message)?,
+            None => writeln!(f, "Error ({}): {}", self.code, self.message)?,
+        }
+        for err in self.errors.iter() {
+            writeln!(
+                f,
+                "    {}: {}, {}{}",
+                err.domain,
+                err.message,
+                err.reason,
+                match &err.location {
+                    &Some(ref loc) => format!("@{}", loc),
+                    &None => String::new(),
+                }
+            )?;
+        }
+        for violation in self.field_violations() {
+            writeln!(f, "    {}: {}", violation.field, violation.description)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ApiError {}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct ServerMessage {
+    #[serde(default)]
     pub domain: String,
+    #[serde(default)]
     pub reason: String,
+    #[serde(default)]
     pub message: String,
     #[serde(rename = "locationType")]
     pub location_type: Option<String>,
     pub location: Option<String>,
 }
 
+/// A typed detail of an `ApiError`, identified by its `@type` when encoded.
+#[derive(Clone, Debug)]
+pub enum ErrorDetail {
+    ErrorInfo(ErrorInfo),
+    RetryInfo(RetryInfo),
+    QuotaFailure(QuotaFailure),
+    BadRequest(BadRequest),
+    Help(Help),
+    /// A detail of any other type, or one which couldn't be decoded, as is.
+    Other(json::Value),
+}
+
+const ERROR_DETAIL_TYPE_PREFIX: &'static str = "type.googleapis.com/google.rpc.";
+
+impl<'de> ::serde::Deserialize<'de> for ErrorDetail {
+    fn deserialize<D: ::serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        fn decode<T: ::serde::de::DeserializeOwned>(
+            value: &json::Value,
+            variant: fn(T) -> ErrorDetail,
+        ) -> Option<ErrorDetail> {
+            json::from_value(value.clone()).ok().map(variant)
+        }
+
+        let value = json::Value::deserialize(deserializer)?;
+        let type_name = value
+            .get("@type")
+            .and_then(json::Value::as_str)
+            .and_then(|t| t.strip_prefix(ERROR_DETAIL_TYPE_PREFIX));
+        let detail = match type_name {
+            Some("ErrorInfo") => decode(&value, ErrorDetail::ErrorInfo),
+            Some("RetryInfo") => decode(&value, ErrorDetail::RetryInfo),
+            Some("QuotaFailure") => decode(&value, ErrorDetail::QuotaFailure),
+            Some("BadRequest") => decode(&value, ErrorDetail::BadRequest),
+            Some("Help") => decode(&value, ErrorDetail::Help),
+            _ => None,
+        };
+        Ok(detail.unwrap_or(ErrorDetail::Other(value)))
+    }
+}
+
+impl ::serde::Serialize for ErrorDetail {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let (type_name, value) = match self {
+            ErrorDetail::ErrorInfo(d) => ("ErrorInfo", json::to_value(d)),
+            ErrorDetail::RetryInfo(d) => ("RetryInfo", json::to_value(d)),
+            ErrorDetail::QuotaFailure(d) => ("QuotaFailure", json::to_value(d)),
+            ErrorDetail::BadRequest(d) => ("BadRequest", json::to_value(d)),
+            ErrorDetail::Help(d) => ("Help", json::to_value(d)),
+            ErrorDetail::Other(value) => return value.serialize(serializer),
+        };
+        let mut value = value.map_err(::serde::ser::Error::custom)?;
+        if let Some(map) = value.as_object_mut() {
+            map.insert(
+                "@type".to_string(),
+                json::Value::String(format!("{}{}", ERROR_DETAIL_TYPE_PREFIX, type_name)),
+            );
+        }
+        value.serialize(serializer)
+    }
+}
+
+/// The cause of an error, as machine readable reason within a domain.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ErrorInfo {
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub domain: String,
+    #[serde(default)]
+    pub metadata: std::collections::HashMap<String, String>,
+}
+
+/// When to retry a failed request.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RetryInfo {
+    #[serde(rename = "retryDelay")]
+    #[serde(default)]
+    #[serde(with = "serde::As::<Option<serde::Duration>>")]
+    pub retry_delay: Option<chrono::Duration>,
+}
+
+/// The quota checks which failed.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct QuotaFailure {
+    #[serde(default)]
+    pub violations: Vec<QuotaViolation>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct QuotaViolation {
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The fields of the request which were invalid.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct BadRequest {
+    #[serde(rename = "fieldViolations")]
+    #[serde(default)]
+    pub field_violations: Vec<FieldViolation>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct FieldViolation {
+    #[serde(default)]
+    pub field: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Links to documentation about the error.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Help {
+    #[serde(default)]
+    pub links: Vec<HelpLink>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct HelpLink {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub url: String,
+}
+
 /// A trait specifying functionality to help controlling any request performed by the API.
 /// The trait has a conservative default implementation.
 ///
 /// It contains methods to deal with all common issues, as well with the ones related to
-/// uploading media
-pub trait Delegate {
+/// uploading media.
+/// Delegates must be `Send`, so that the futures of the calls they are used with can be moved between threads.
+pub trait Delegate: Send {
     /// Called at the beginning of any API request. The delegate should store the method
     /// information if he is interesting in knowing more context when further calls to it
     /// are made.
@@ -114,29 +721,38 @@ pub trait Delegate {
     /// between various API calls.
     fn begin(&mut self, _info: MethodInfo) {}
 
-    /// Called whenever there is an [HttpError](hyper::Error), usually if there are network problems.
+    /// Called whenever the hub's `Transport` fails to make a request, usually if there are network
+    /// problems. Errors of a `hyper::Client` can be obtained using `err.downcast_ref::<hyper::Error>()`.
     ///
     /// If you choose to retry after a duration, the duration should be chosen using the
     /// [exponential backoff algorithm](http://en.wikipedia.org/wiki/Exponential_backoff).
     ///
     /// Return retry information.
-    fn http_error(&mut self, _err: &hyper::Error) -> Retry {
+    fn http_error(&mut self, _err: &(dyn error::Error + Send + Sync + 'static)) -> Retry {
+        Retry::Abort
+    }
+
+    /// Called whenever an attempt took longer than the given timeout, e.g. because the connection hung
+    /// or the response body arrived too slowly.
+    ///
+    /// Return retry information.
+    fn timeout(&mut self, _timeout: Duration) -> Retry {
         Retry::Abort
     }
 
     /// Called whenever there is the need for your applications API key after
-    /// the official authenticator implementation didn't provide one, for some reason.
+    /// the hub's `GetToken` implementation didn't provide one, for some reason.
     /// If this method returns None as well, the underlying operation will fail
     fn api_key(&mut self) -> Option<String> {
         None
     }
 
-    /// Called whenever the Authenticator didn't yield a token. The delegate
+    /// Called whenever the hub's `GetToken` implementation failed to yield a token. The delegate
     /// may attempt to provide one, or just take it as a general information about the
     /// impending failure.
     /// The given Error provides information about why the token couldn't be acquired in the
     /// first place
-    fn token(&mut self, err: &oauth2::Error) -> Option<oauth2::AccessToken> {
+    fn token(&mut self, err: &(dyn error::Error + Send + Sync)) -> Option<String> {
         let _ = err;
         None
     }
@@ -162,6 +778,22 @@ pub trait Delegate {
         let _ = url;
     }
 
+    /// Called when a resumable upload was started, whenever the server confirmed to have received
+    /// more of its bytes, and if it was interrupted. Persisting the session allows to continue the upload
+    /// later, even after a restart of the process, using the `upload_resumable_from_session(...)` method
+    /// of the call which started it.
+    /// It will be called with None once the upload finished, as the session can't be resumed anymore.
+    fn store_upload_session(&mut self, session: Option<&UploadSession>) {
+        let _ = session;
+    }
+
+    /// Called during resumable uploads whenever the server confirmed the amount of bytes it received so far.
+    /// It can be used to display the progress of the upload.
+    fn upload_progress(&mut self, confirmed_bytes: u64, total_size: u64) {
+        let _ = confirmed_bytes;
+        let _ = total_size;
+    }
+
     /// Called whenever a server response could not be decoded from json.
     /// It's for informational purposes only, the caller will return with an error
     /// accordingly.
@@ -191,7 +823,7 @@ pub trait Delegate {
         &mut self,
         _: &hyper::Response<hyper::body::Body>,
         _err: Option<JsonServerError>,
-        _: Option<ServerError>,
+        _: Option<ApiError>,
     ) -> Retry {
         Retry::Abort
     }
@@ -237,50 +869,507 @@ pub struct DefaultDelegate;
 
 impl Delegate for DefaultDelegate {}
 
-#[derive(Debug)]
-pub enum Error {
-    /// The http connection failed
-    HttpError(hyper::Error),
+/// Status codes which indicate a transient problem on the server side, and which are worth retrying.
+const RETRYABLE_STATUS_CODES: [StatusCode; 5] = [
+    StatusCode::TOO_MANY_REQUESTS,
+    StatusCode::INTERNAL_SERVER_ERROR,
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+/// A delegate which retries failed requests using a jittered
+/// [exponential backoff](http://en.wikipedia.org/wiki/Exponential_backoff), honoring the
+/// `Retry-After` header if the server sends one.
+///
+/// Only network errors and responses indicating a transient problem are retried, see
+/// `RetryPolicy::is_retryable()`. Retries stop once either the maximum amount of attempts or the
+/// maximum elapsed time is reached, whichever comes first.
+///
+/// It can be used as delegate directly, or be embedded into your own delegate. In the latter case,
+/// call `reset()` in `Delegate::begin()` and forward `http_error(...)`, `timeout(...)` and `http_failure(...)` to it.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    max_attempts: u32,
+    max_elapsed: Option<Duration>,
+
+    attempts: u32,
+    started_at: Option<Instant>,
+}
 
-    /// An attempt was made to upload a resource with size stored in field `.0`
-    /// even though the maximum upload size is what is stored in field `.1`.
-    UploadSizeLimitExceeded(u64, u64),
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(32),
+            multiplier: 2.0,
+            max_attempts: 5,
+            max_elapsed: Some(Duration::from_secs(60)),
+            attempts: 0,
+            started_at: None,
+        }
+    }
+}
 
-    /// Represents information about a request that was not understood by the server.
-    /// Details are included.
-    BadRequest(ErrorResponse),
+impl RetryPolicy {
+    /// Set the delay before the first retry, which is multiplied for each subsequent one.
+    /// It defaults to 500ms.
+    pub fn initial_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.initial_backoff = backoff;
+        self
+    }
 
-    /// We needed an API key for authentication, but didn't obtain one.
-    /// Neither through the authenticator, nor through the Delegate.
-    MissingAPIKey,
+    /// Set the maximum delay between two attempts. It defaults to 32s.
+    pub fn max_backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.max_backoff = backoff;
+        self
+    }
 
-    /// We required a Token, but didn't get one from the Authenticator
-    MissingToken(oauth2::Error),
+    /// Set the factor by which the delay grows with each retry. It defaults to 2.
+    ///
+    /// # Panics
+    ///
+    /// If the multiplier is below 1 or not finite.
+    pub fn multiplier(mut self, multiplier: f64) -> RetryPolicy {
+        assert!(
+            multiplier.is_finite() && multiplier >= 1.0,
+            "the backoff multiplier must be a finite number of at least 1, got {}",
+            multiplier
+        );
+        self.multiplier = multiplier;
+        self
+    }
 
-    /// The delgate instructed to cancel the operation
-    Cancelled,
+    /// Set the maximum amount of attempts, including the first one. It defaults to 5.
+    pub fn max_attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.max_attempts = attempts;
+        self
+    }
 
-    /// An additional, free form field clashed with one of the built-in optional ones
-    FieldClash(&'static str),
+    /// Set the time after which no further retries are made, measured from the first failure.
+    /// `None` disables this budget. It defaults to 60s.
+    pub fn max_elapsed(mut self, elapsed: Option<Duration>) -> RetryPolicy {
+        self.max_elapsed = elapsed;
+        self
+    }
 
-    /// Shows that we failed to decode the server response.
-    /// This can happen if the protocol changes in conjunction with strict json decoding.
-    JsonDecodeError(String, json::Error),
+    /// Forget about previous attempts. Must be called before each new API request.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.started_at = None;
+    }
 
-    /// Indicates an HTTP repsonse with a non-success status code
-    Failure(hyper::Response<hyper::body::Body>),
+    /// Returns true if a response with the given status and decoded error indicates a transient
+    /// problem, which may go away when trying again.
+    pub fn is_retryable(status: StatusCode, server_error: Option<&ApiError>) -> bool {
+        RETRYABLE_STATUS_CODES.contains(&status)
+            || server_error.map_or(false, ApiError::is_rate_limited)
+    }
+
+    /// Decide whether to retry after the given network error.
+    pub fn on_http_error(&mut self, _err: &(dyn error::Error + Send + Sync + 'static)) -> Retry {
+        self.next_retry(None)
+    }
+
+    /// Decide whether to retry after an attempt timed out.
+    pub fn on_timeout(&mut self, _timeout: Duration) -> Retry {
+        self.next_retry(None)
+    }
+
+    /// Decide whether to retry after the given failed response.
+    pub fn on_http_failure(
+        &mut self,
+        response: &hyper::Response<hyper::body::Body>,
+        server_error: Option<&ApiError>,
+    ) -> Retry {
+        if !RetryPolicy::is_retryable(response.status(), server_error) {
+            return Retry::Abort;
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after)
+            .or_else(|| server_error.and_then(ApiError::retry_delay));
+        self.next_retry(retry_after)
+    }
+
+    /// Account for another attempt and compute the delay before making it, if the budget allows.
+    fn next_retry(&mut self, retry_after: Option<Duration>) -> Retry {
+        let started_at = *self.started_at.get_or_insert_with(Instant::now);
+        self.attempts += 1;
+        if self.attempts >= self.max_attempts {
+            return Retry::Abort;
+        }
+
+        let delay = match retry_after {
+            Some(d) => d,
+            None => {
+                // clamp in floating point, as the exponential quickly exceeds what a Duration can hold
+                let exp = self.multiplier.powi(self.attempts as i32 - 1);
+                let max_backoff = self.max_backoff.as_secs_f64();
+                let backoff = (self.initial_backoff.as_secs_f64() * exp).min(max_backoff);
+                let backoff = Duration::try_from_secs_f64(backoff)
+                    .unwrap_or(self.max_backoff)
+                    .as_millis() as u64;
+                // equal jitter: never wait less than half of the backoff
+                Duration::from_millis(backoff / 2 + rand::thread_rng().gen_range(0..=backoff / 2))
+            }
+        };
+
+        if let Some(max_elapsed) = self.max_elapsed {
+            if started_at.elapsed().saturating_add(delay) > max_elapsed {
+                return Retry::Abort;
+            }
+        }
+        Retry::After(delay)
+    }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Error::HttpError(ref err) => err.fmt(f),
-            Error::UploadSizeLimitExceeded(ref resource_size, ref max_size) => writeln!(
-                f,
-                "The media size {} exceeds the maximum allowed upload size of {}",
-                resource_size, max_size
-            ),
-            Error::MissingAPIKey => {
+impl Delegate for RetryPolicy {
+    fn begin(&mut self, _info: MethodInfo) {
+        self.reset();
+    }
+
+    fn http_error(&mut self, err: &(dyn error::Error + Send + Sync + 'static)) -> Retry {
+        self.on_http_error(err)
+    }
+
+    fn timeout(&mut self, timeout: Duration) -> Retry {
+        self.on_timeout(timeout)
+    }
+
+    fn http_failure(
+        &mut self,
+        response: &hyper::Response<hyper::body::Body>,
+        _err: Option<JsonServerError>,
+        server_error: Option<ApiError>,
+    ) -> Retry {
+        self.on_http_failure(response, server_error.as_ref())
+    }
+}
+
+/// Parse the value of a `Retry-After` header, which is either an amount of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::from_secs(0)),
+    )
+}
+
+/// Signals that waiting for a long-running operation should stop, see `PollOptions::cancellation()`.
+///
+/// Clones share their state, so a token can be cancelled from another task than the one waiting.
+/// Please note that this only stops the waiting, the operation keeps running on the server unless the
+/// API's method to cancel it is called.
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+    sender: std::sync::Arc<tokio::sync::watch::Sender<bool>>,
+    receiver: tokio::sync::watch::Receiver<bool>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> CancellationToken {
+        let (sender, receiver) = tokio::sync::watch::channel(false);
+        CancellationToken {
+            sender: std::sync::Arc::new(sender),
+            receiver,
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    /// Cancel all waiting using this token, or any of its clones.
+    pub fn cancel(&self) {
+        let _ = self.sender.send(true);
+    }
+
+    /// Returns true if `cancel()` was called.
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once `cancel()` is called.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // can't happen, as we hold the sender
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Configures how a long-running operation is polled until it is done, as used by the `wait()` and
+/// `poll_until_done()` methods of the operations an API returns.
+///
+/// The delay between two polls grows exponentially, from the initial delay up to the maximum one.
+/// Waiting fails with `Error::Timeout` once the timeout passed, and with `Error::Cancelled` if the
+/// cancellation token is cancelled.
+#[derive(Debug, Clone)]
+pub struct PollOptions {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    timeout: Option<Duration>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl Default for PollOptions {
+    fn default() -> PollOptions {
+        PollOptions {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 1.5,
+            timeout: None,
+            cancellation: None,
+        }
+    }
+}
+
+impl PollOptions {
+    /// Set the delay before the first poll, which is multiplied for each subsequent one.
+    /// It defaults to 1s.
+    pub fn initial_delay(mut self, delay: Duration) -> PollOptions {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set the maximum delay between two polls. It defaults to 30s.
+    pub fn max_delay(mut self, delay: Duration) -> PollOptions {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the factor by which the delay grows with each poll. It defaults to 1.5.
+    ///
+    /// # Panics
+    ///
+    /// If the multiplier is below 1 or not finite.
+    pub fn multiplier(mut self, multiplier: f64) -> PollOptions {
+        assert!(
+            multiplier.is_finite() && multiplier >= 1.0,
+            "the poll delay multiplier must be a finite number of at least 1, got {}",
+            multiplier
+        );
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the time after which waiting fails with `Error::Timeout`, measured from the start of the
+    /// waiting. `None` waits until the operation is done. It defaults to `None`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> PollOptions {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the token to stop waiting with, failing with `Error::Cancelled`.
+    pub fn cancellation(mut self, token: CancellationToken) -> PollOptions {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Sleep for the given delay, returning false if the cancellation token was cancelled meanwhile.
+    async fn sleep(&self, delay: Duration) -> bool {
+        match self.cancellation {
+            Some(ref token) => {
+                let sleep = Box::pin(tokio::time::sleep(delay));
+                let cancelled = Box::pin(token.cancelled());
+                match futures::future::select(sleep, cancelled).await {
+                    futures::future::Either::Left(_) => !token.is_cancelled(),
+                    futures::future::Either::Right(_) => false,
+                }
+            }
+            None => {
+                tokio::time::sleep(delay).await;
+                true
+            }
+        }
+    }
+}
+
+/// Poll a long-running operation until `is_done` returns true for it, obtaining its latest state
+/// using `poll`, which is called with the previous one.
+///
+/// This is used by the generated `poll_until_done()` methods of operations.
+pub async fn poll_until_done<T, P, F>(
+    mut operation: T,
+    options: &PollOptions,
+    is_done: fn(&T) -> bool,
+    mut poll: P,
+) -> Result<T>
+where
+    P: FnMut(&T) -> F,
+    F: Future<Output = Result<T>>,
+{
+    let started_at = tokio::time::Instant::now();
+    let mut delay = options.initial_delay;
+    while !is_done(&operation) {
+        if options.cancellation.as_ref().map_or(false, CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        let mut sleep = delay;
+        if let Some(timeout) = options.timeout {
+            let elapsed = started_at.elapsed();
+            if elapsed >= timeout {
+                return Err(Error::Timeout(timeout));
+            }
+            // poll a last time when the time is up
+            sleep = sleep.min(timeout - elapsed);
+        }
+        if !options.sleep(sleep).await {
+            return Err(Error::Cancelled);
+        }
+        operation = poll(&operation).await?;
+        // clamp in floating point, as the product may exceed what a Duration can hold
+        let next = (delay.as_secs_f64() * options.multiplier).min(options.max_delay.as_secs_f64());
+        delay = Duration::try_from_secs_f64(next).unwrap_or(options.max_delay);
+    }
+    Ok(operation)
+}
+
+/// Returns the id following the given collection in a resource name or url, like `zone-a` for `zones`
+/// in `projects/p/zones/zone-a/operations/o`.
+pub fn resource_id<'a>(name: &'a str, collection: &str) -> Option<&'a str> {
+    let path = name.split(&['?', '#'][..]).next().unwrap_or_default();
+    let mut segments = path.split('/');
+    while let Some(segment) = segments.next() {
+        if segment == collection {
+            return segments.next().filter(|id| !id.is_empty());
+        }
+    }
+    None
+}
+
+/// Returns true if the resource name matches the pattern a discovery document describes it with,
+/// like `^projects/[^/]+/operations/[^/]+$`.
+///
+/// Only the constructs used in such patterns are understood, for any other pattern it returns true
+/// unless the name is empty.
+pub fn matches_name_pattern(name: &str, pattern: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    // a single segment, which can't be split at its slash
+    let pattern = pattern
+        .trim_start_matches('^')
+        .trim_end_matches('$')
+        .replace("[^/]+", "*");
+    let mut segments = name.split('/');
+    for expected in pattern.split('/') {
+        match expected {
+            ".*" => return true,
+            ".+" => return segments.next().map_or(false, |s| !s.is_empty()),
+            "*" => {
+                if segments.next().map_or(true, str::is_empty) {
+                    return false;
+                }
+            }
+            literal if literal.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') => {
+                if segments.next() != Some(literal) {
+                    return false;
+                }
+            }
+            _ => return true,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Decode a field of type `Any`, like the `response` or `metadata` of an operation, into the given type.
+/// The `@type` identifying the message is ignored.
+pub fn decode_any<T: ::serde::de::DeserializeOwned>(
+    value: &std::collections::HashMap<String, json::Value>,
+) -> Result<T> {
+    let object: json::Map<String, json::Value> = value
+        .iter()
+        .filter(|(k, _)| k.as_str() != "@type")
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let value = json::Value::Object(object);
+    json::from_value(value.clone()).map_err(|err| Error::JsonDecodeError(value.to_string(), err))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The http connection failed
+    HttpError(TransportError),
+
+    /// An attempt took longer than the timeout stored in field `.0`, and wasn't retried, or a long-running
+    /// operation wasn't done within the timeout of its `PollOptions`.
+    Timeout(Duration),
+
+    /// An attempt was made to upload a resource with size stored in field `.0`
+    /// even though the maximum upload size is what is stored in field `.1`.
+    UploadSizeLimitExceeded(u64, u64),
+
+    /// The server failed the request, and described why.
+    Api(ApiError),
+
+    /// We needed an API key for authentication, but didn't obtain one.
+    /// Neither through the authenticator, nor through the Delegate.
+    MissingAPIKey,
+
+    /// We required a Token, but didn't get one from the hub's `GetToken` implementation
+    MissingToken(GetTokenError),
+
+    /// The delgate instructed to cancel the operation, or waiting for a long-running operation was cancelled
+    Cancelled,
+
+    /// An additional, free form field clashed with one of the built-in optional ones
+    FieldClash(&'static str),
+
+    /// Shows that we failed to decode the server response.
+    /// This can happen if the protocol changes in conjunction with strict json decoding.
+    JsonDecodeError(String, json::Error),
+
+    /// Indicates an HTTP repsonse with a non-success status code
+    Failure(hyper::Response<hyper::body::Body>),
+
+    /// The server answered a conditional request with `304 Not Modified`, e.g. as the resource still
+    /// matches the etag passed to `if_none_match()`.
+    NotModified(hyper::Response<hyper::body::Body>),
+
+    /// The response to a batch request could not be understood, or lacked the response to a call.
+    BatchResponse(String),
+
+    /// An `UploadSession` can't be used to resume the upload, e.g. as it belongs to another method.
+    InvalidUploadSession(String),
+
+    /// A long-running operation finished, but failed as described.
+    Operation(ApiError),
+
+    /// A long-running operation can't be polled, e.g. as its name doesn't identify it.
+    InvalidOperation(String),
+
+    /// An `Endpoint` can't be used by a hub, e.g. as its client can't present the client certificate.
+    InvalidEndpoint(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::HttpError(ref err) => err.fmt(f),
+            Error::Timeout(ref timeout) => writeln!(f, "The request timed out after {:?}", timeout),
+            Error::UploadSizeLimitExceeded(ref resource_size, ref max_size) => writeln!(
+                f,
+                "The media size {} exceeds the maximum allowed upload size of {}",
+                resource_size, max_size
+            ),
+            Error::MissingAPIKey => {
                 (writeln!(
                     f,
                     "The application's API key was not found in the configuration"
@@ -291,27 +1380,11 @@ impl Display for Error {
                     "It is used as there are no Scopes defined for this method."
                 )
             }
-            Error::BadRequest(ref err) => {
-                writeln!(f, "Bad Request ({}): {}", err.error.code, err.error.message)?;
-                for err in err.error.errors.iter() {
-                    writeln!(
-                        f,
-                        "    {}: {}, {}{}",
-                        err.domain,
-                        err.message,
-                        err.reason,
-                        match &err.location {
-                            &Some(ref loc) => format!("@{}", loc),
-                            &None => String::new(),
-                        }
-                    )?;
-                }
-                Ok(())
-            }
+            Error::Api(ref err) => err.fmt(f),
             Error::MissingToken(ref err) => {
                 writeln!(f, "Token retrieval failed with error: {}", err)
             }
-            Error::Cancelled => writeln!(f, "Operation cancelled by delegate"),
+            Error::Cancelled => writeln!(f, "Operation cancelled"),
             Error::FieldClash(field) => writeln!(
                 f,
                 "The custom parameter '{}' is already provided natively by the CallBuilder.",
@@ -321,6 +1394,12 @@ impl Display for Error {
             Error::Failure(ref response) => {
                 writeln!(f, "Http status indicates failure: {:?}", response)
             }
+            Error::NotModified(_) => writeln!(f, "The resource was not modified"),
+            Error::BatchResponse(ref msg) => writeln!(f, "Invalid batch response: {}", msg),
+            Error::InvalidUploadSession(ref msg) => writeln!(f, "Invalid upload session: {}", msg),
+            Error::Operation(ref err) => write!(f, "The operation failed: {}", err),
+            Error::InvalidOperation(ref msg) => writeln!(f, "Invalid operation: {}", msg),
+            Error::InvalidEndpoint(ref msg) => writeln!(f, "Invalid endpoint: {}", msg),
         }
     }
 }
@@ -335,29 +1414,383 @@ impl error::Error for Error {
     }
 }
 
+impl Error {
+    /// The error the server described, if this is an `Error::Api` or an `Error::Operation`.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match *self {
+            Error::Api(ref err) | Error::Operation(ref err) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this is an `Error::NotModified`, i.e. a cached copy of the resource is still valid.
+    pub fn is_not_modified(&self) -> bool {
+        match *self {
+            Error::NotModified(_) => true,
+            _ => false,
+        }
+    }
+}
+
 /// A universal result type used as return for all calls.
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Contains information about an API request.
+///
+/// Requests sent through the `Transport` carry it as extension.
+#[derive(Clone, Debug)]
 pub struct MethodInfo {
     pub id: &'static str,
     pub http_method: Method,
 }
 
+/// The header naming the project which is billed for the quota of a request, instead of the one of the credentials.
+pub const QUOTA_PROJECT_HEADER: &'static str = "x-goog-user-project";
+
+/// The header telling the reason of a request, which is recorded in the audit logs of the resource.
+pub const REQUEST_REASON_HEADER: &'static str = "x-goog-request-reason";
+
+/// The header identifying the library which makes a request.
+pub const API_CLIENT_HEADER: &'static str = "x-goog-api-client";
+
+/// Sets the header with the given name to `value`, or removes it if `value` is `None`.
+/// Returns the value it had before.
+pub fn replace_header(
+    headers: &mut HeaderMap,
+    name: &'static str,
+    value: Option<HeaderValue>,
+) -> Option<HeaderValue> {
+    match value {
+        Some(value) => headers.insert(name, value),
+        None => headers.remove(name),
+    }
+}
+
+/// Returns the headers of the hub, with the ones of the call taking precedence.
+pub fn merge_headers(hub: &HeaderMap, call: &HeaderMap) -> HeaderMap {
+    let mut headers = hub.clone();
+    for name in call.keys() {
+        headers.remove(name);
+        for value in call.get_all(name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    headers
+}
+
+/// Returns the headers which must be sent with each request of a resumable upload, like the quota project.
+/// Others, like conditions, only apply to the request starting it.
+pub fn upload_headers(headers: &HeaderMap) -> HeaderMap {
+    headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-goog-"))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// The domain of the Google APIs, which is replaced by the universe domain of an `Endpoint`.
+pub const DEFAULT_UNIVERSE_DOMAIN: &'static str = "googleapis.com";
+
+/// Where the requests of a hub are sent, based on the URLs of the discovery document of its API.
+///
+/// The URLs of all requests, including uploads and batch requests, are derived from the root URL it yields.
+/// It depends on whether mutual TLS is used, on the region, for services offering regional endpoints like
+/// `europe-west1-run.googleapis.com`, and on the universe domain, which replaces `googleapis.com`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    root_url: String,
+    mtls_root_url: Option<String>,
+    service_path: String,
+    universe_domain: Option<String>,
+    region: Option<String>,
+    client_certificate: Option<ClientCertificate>,
+}
+
+/// A certificate chain and private key, both PEM encoded.
+#[derive(Clone, PartialEq)]
+struct ClientCertificate {
+    cert_chain: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl fmt::Debug for ClientCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientCertificate").finish()
+    }
+}
+
+impl Endpoint {
+    /// An endpoint using the given `rootUrl`, `servicePath` and `mtlsRootUrl` of a discovery document.
+    pub fn new(root_url: &str, service_path: &str, mtls_root_url: Option<&str>) -> Endpoint {
+        Endpoint {
+            root_url: root_url.to_string(),
+            mtls_root_url: mtls_root_url.map(str::to_string),
+            service_path: service_path.to_string(),
+            universe_domain: None,
+            region: None,
+            client_certificate: None,
+        }
+    }
+
+    /// Send requests to the given universe domain, like `example-cloud.com`, instead of `googleapis.com`.
+    pub fn universe_domain<D: Into<String>>(mut self, domain: D) -> Endpoint {
+        self.universe_domain = Some(domain.into());
+        self
+    }
+
+    /// Send requests to the endpoint of the given region, like `europe-west1`.
+    /// Please note that only some services offer regional endpoints.
+    pub fn region<R: Into<String>>(mut self, region: R) -> Endpoint {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Use mutual TLS, authenticating with the given PEM encoded certificate chain and private key.
+    /// Requests are sent to the mTLS root URL of the API, by a hub whose client presents the certificate,
+    /// as set up by its `https_endpoint()` method.
+    pub fn mtls(mut self, cert_chain_pem: &[u8], private_key_pem: &[u8]) -> Endpoint {
+        self.client_certificate = Some(ClientCertificate {
+            cert_chain: cert_chain_pem.to_vec(),
+            private_key: private_key_pem.to_vec(),
+        });
+        self
+    }
+
+    /// Returns true if mutual TLS is used.
+    pub fn is_mtls(&self) -> bool {
+        self.client_certificate.is_some()
+    }
+
+    /// The root URL all requests are relative to, like `https://www.googleapis.com/`.
+    pub fn root_url(&self) -> String {
+        let url = match self.mtls_root_url {
+            Some(ref mtls_root_url) if self.is_mtls() => mtls_root_url,
+            _ => &self.root_url,
+        };
+        let (scheme, rest) = match url.find("://") {
+            Some(pos) => (&url[..pos + 3], &url[pos + 3..]),
+            None => return url.clone(),
+        };
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let mut host = host.to_string();
+        if let Some(ref domain) = self.universe_domain {
+            if host.ends_with(DEFAULT_UNIVERSE_DOMAIN) {
+                host.truncate(host.len() - DEFAULT_UNIVERSE_DOMAIN.len());
+                host.push_str(domain);
+            }
+        }
+        if let Some(ref region) = self.region {
+            host = format!("{}-{}", region, host);
+        }
+        format!("{}{}{}", scheme, host, path)
+    }
+
+    /// The URL of the service, i.e. the root URL followed by the service path, like `https://www.googleapis.com/drive/v3/`.
+    pub fn base_url(&self) -> String {
+        self.root_url() + &self.service_path
+    }
+
+    /// An HTTPS connector trusting the root certificates of the system, which presents the client certificate
+    /// if mutual TLS is used.
+    pub fn https_connector(
+        &self,
+    ) -> std::result::Result<
+        hyper_rustls::HttpsConnector<hyper::client::HttpConnector>,
+        TransportError,
+    > {
+        let config = self.tls_config(native_root_store()?)?;
+        Ok(https_connector(config))
+    }
+
+    /// The TLS configuration of `https_connector()`, trusting the given root certificates instead of the ones
+    /// of the system.
+    pub fn tls_config(
+        &self,
+        root_store: rustls::RootCertStore,
+    ) -> std::result::Result<rustls::ClientConfig, TransportError> {
+        let mut config = rustls::ClientConfig::new();
+        config.root_store = root_store;
+        if let Some(ref certificate) = self.client_certificate {
+            let cert_chain = rustls::internal::pemfile::certs(&mut &certificate.cert_chain[..])
+                .map_err(|_| "invalid client certificate chain")?;
+            let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut &certificate.private_key[..])
+                .map_err(|_| "invalid client private key")?;
+            if keys.is_empty() {
+                keys = rustls::internal::pemfile::rsa_private_keys(&mut &certificate.private_key[..])
+                    .map_err(|_| "invalid client private key")?;
+            }
+            let key = keys.pop().ok_or("no private key found for the client certificate")?;
+            config.set_single_client_cert(cert_chain, key)?;
+        }
+        Ok(config)
+    }
+}
+
+/// The root certificates of the system. Those which can't be parsed are skipped.
+fn native_root_store() -> io::Result<rustls::RootCertStore> {
+    match rustls_native_certs::load_native_certs() {
+        Ok(store) | Err((Some(store), _)) => Ok(store),
+        Err((None, err)) => Err(err),
+    }
+}
+
+/// An HTTPS connector using the given TLS configuration, which also allows plain HTTP.
+fn https_connector(
+    mut config: rustls::ClientConfig,
+) -> hyper_rustls::HttpsConnector<hyper::client::HttpConnector> {
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let mut http = hyper::client::HttpConnector::new();
+    http.enforce_http(false);
+    (http, config).into()
+}
+
+/// Query parameters whose values are replaced by `REDACTED` before a URL is traced.
+const REDACTED_PARAMS: [&'static str; 4] = ["key", "access_token", "oauth_token", "upload_id"];
+
+/// Creates the `tracing` span an API call is made in.
+///
+/// Besides identifying the method, it records the number of the current attempt, and the status code,
+/// latency and bytes sent and received of the last one. Use `CallTrace` to fill them in.
+pub fn call_span(id: &'static str, http_method: &Method, url_template: &str) -> tracing::Span {
+    tracing::info_span!(
+        "google_api_call",
+        method.id = id,
+        http.method = %http_method,
+        url.template = url_template,
+        attempt = tracing::field::Empty,
+        http.status_code = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+        bytes_sent = tracing::field::Empty,
+        bytes_received = tracing::field::Empty,
+    )
+}
+
+/// Returns the given URL with the values of query parameters carrying secrets, like API keys, replaced.
+pub fn redact_url(url: &str) -> String {
+    let (base, query) = match url.find('?') {
+        Some(pos) => (&url[..pos], &url[pos + 1..]),
+        None => return url.to_string(),
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.find('=') {
+            Some(pos) if REDACTED_PARAMS.contains(&&pair[..pos]) => {
+                format!("{}=REDACTED", &pair[..pos])
+            }
+            _ => pair.to_string(),
+        })
+        .join("&");
+    format!("{}?{}", base, query)
+}
+
+/// Records the attempts of an API call to the span created by `call_span()`, and emits an event for each.
+///
+/// Neither headers nor request or response bodies are traced, and URLs are passed through `redact_url()`,
+/// so no tokens or other secrets end up in the trace.
+pub struct CallTrace {
+    span: tracing::Span,
+    attempt: u32,
+    started_at: Instant,
+}
+
+impl CallTrace {
+    /// Trace into the current span, which is expected to be the one created by `call_span()`.
+    pub fn current() -> CallTrace {
+        CallTrace {
+            span: tracing::Span::current(),
+            attempt: 0,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Called right before sending the request of the next attempt to the given URL.
+    pub fn attempt(&mut self, url: &str, bytes_sent: u64) {
+        self.attempt += 1;
+        self.started_at = Instant::now();
+        self.span.record("attempt", &self.attempt);
+        self.span.record("bytes_sent", &bytes_sent);
+        tracing::debug!(
+            parent: &self.span,
+            attempt = self.attempt,
+            url = %redact_url(url),
+            bytes_sent,
+            "sending request"
+        );
+    }
+
+    /// Called once the response headers of the current attempt arrived.
+    pub fn response(&self, res: &hyper::Response<hyper::body::Body>) {
+        let latency_ms = self.started_at.elapsed().as_millis() as u64;
+        self.span.record("http.status_code", &res.status().as_u16());
+        self.span.record("latency_ms", &latency_ms);
+        if let Some(len) = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            self.span.record("bytes_received", &len);
+        }
+        tracing::debug!(
+            parent: &self.span,
+            attempt = self.attempt,
+            http.status_code = res.status().as_u16(),
+            latency_ms,
+            "received response"
+        );
+    }
+
+    /// Called once the response body was read, with its size.
+    pub fn received(&self, bytes_received: usize) {
+        self.span.record("bytes_received", &(bytes_received as u64));
+    }
+
+    /// Called if the current attempt took longer than the given timeout.
+    pub fn timeout(&self, timeout: Duration) {
+        tracing::warn!(
+            parent: &self.span,
+            attempt = self.attempt,
+            ?timeout,
+            "request timed out"
+        );
+    }
+
+    /// Called if the current attempt failed without a response.
+    pub fn error(&self, err: &(dyn error::Error + Send + Sync + 'static)) {
+        tracing::warn!(
+            parent: &self.span,
+            attempt = self.attempt,
+            latency_ms = self.started_at.elapsed().as_millis() as u64,
+            error = %err,
+            "request failed"
+        );
+    }
+}
+
 const BOUNDARY: &'static str = "MDuXWGyeE33QFXGchb2VFWc4Z7945d";
 
-/// Provides a `Read` interface that converts multiple parts into the protocol
-/// identified by [RFC2387](https://tools.ietf.org/html/rfc2387).
+/// Provides an `AsyncRead` interface that converts multiple parts into the protocol
+/// identified by [RFC2387](https://tools.ietf.org/html/rfc2387), or into a `multipart/mixed`
+/// message as used by batch requests.
 /// **Note**: This implementation is just as rich as it needs to be to perform uploads
 /// to google APIs, and might not be a fully-featured implementation.
 #[derive(Default)]
 pub struct MultiPartReader<'a> {
-    raw_parts: Vec<(HeaderMap, &'a mut dyn Read)>,
-    current_part: Option<(Cursor<Vec<u8>>, &'a mut dyn Read)>,
+    raw_parts: Vec<(HeaderMap, u64, &'a mut (dyn AsyncRead + Unpin + Send))>,
+    current_part: Option<(Cursor<Vec<u8>>, &'a mut (dyn AsyncRead + Unpin + Send))>,
     last_part_boundary: Option<Cursor<Vec<u8>>>,
+    mixed: bool,
 }
 
 impl<'a> MultiPartReader<'a> {
+    /// Returns a reader producing a `multipart/mixed` message, instead of a `multipart/related` one.
+    pub fn mixed() -> MultiPartReader<'a> {
+        MultiPartReader {
+            mixed: true,
+            ..Default::default()
+        }
+    }
+
     /// Reserve memory for exactly the given amount of parts
     pub fn reserve_exact(&mut self, cap: usize) {
         self.raw_parts.reserve_exact(cap);
@@ -376,7 +1809,7 @@ impl<'a> MultiPartReader<'a> {
     /// `mime`    - It will be put onto the content type
     pub fn add_part(
         &mut self,
-        reader: &'a mut dyn Read,
+        reader: &'a mut (dyn AsyncRead + Unpin + Send),
         size: u64,
         mime_type: Mime,
     ) -> &mut MultiPartReader<'a> {
@@ -386,7 +1819,17 @@ impl<'a> MultiPartReader<'a> {
             hyper::header::HeaderValue::from_str(&format!("{}", mime_type)).unwrap(),
         );
         headers.insert(CONTENT_LENGTH, size.into());
-        self.raw_parts.push((headers, reader));
+        self.add_part_with_headers(reader, size, headers)
+    }
+
+    /// Like `add_part()`, but the part's `headers` are used as given.
+    pub fn add_part_with_headers(
+        &mut self,
+        reader: &'a mut (dyn AsyncRead + Unpin + Send),
+        size: u64,
+        headers: HeaderMap,
+    ) -> &mut MultiPartReader<'a> {
+        self.raw_parts.push((headers, size, reader));
         self
     }
 
@@ -395,7 +1838,7 @@ impl<'a> MultiPartReader<'a> {
     pub fn mime_type(&self) -> Mime {
         Mime(
             TopLevel::Multipart,
-            SubLevel::Ext("Related".to_string()),
+            SubLevel::Ext(if self.mixed { "mixed" } else { "Related" }.to_string()),
             vec![(
                 Attr::Ext("boundary".to_string()),
                 Value::Ext(BOUNDARY.to_string()),
@@ -403,101 +1846,143 @@ impl<'a> MultiPartReader<'a> {
         )
     }
 
-    /// Returns true if we are totally used
-    fn is_depleted(&self) -> bool {
-        self.raw_parts.len() == 0
-            && self.current_part.is_none()
-            && self.last_part_boundary.is_none()
-    }
-
-    /// Returns true if we are handling our last part
-    fn is_last_part(&self) -> bool {
-        self.raw_parts.len() == 0 && self.current_part.is_some()
-    }
-}
-
-impl<'a> Read for MultiPartReader<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match (
-            self.raw_parts.len(),
-            self.current_part.is_none(),
-            self.last_part_boundary.is_none(),
-        ) {
-            (_, _, false) => {
-                let br = self
-                    .last_part_boundary
-                    .as_mut()
-                    .unwrap()
-                    .read(buf)
-                    .unwrap_or(0);
-                if br < buf.len() {
-                    self.last_part_boundary = None;
-                }
-                return Ok(br);
-            }
-            (0, true, true) => return Ok(0),
-            (n, true, _) if n > 0 => {
-                let (headers, reader) = self.raw_parts.remove(0);
-                let mut c = Cursor::new(Vec::<u8>::new());
-                (write!(
-                    &mut c,
-                    "{}--{}{}{}{}",
-                    LINE_ENDING,
-                    BOUNDARY,
-                    LINE_ENDING,
-                    headers
-                        .iter()
-                        .map(|(k, v)| format!("{}: {}", k, v.to_str().unwrap()))
-                        .join(LINE_ENDING),
-                    LINE_ENDING
-                ))
-                .unwrap();
-                c.seek(SeekFrom::Start(0)).unwrap();
-                self.current_part = Some((c, reader));
-            }
-            _ => {}
+    /// Returns the total amount of bytes this reader will produce, assuming all readers
+    /// provide exactly as many bytes as they were announced with.
+    /// Use it with the ContentLength header, before reading.
+    pub fn content_length(&self) -> u64 {
+        if self.raw_parts.is_empty() {
+            return 0;
         }
+        self.raw_parts
+            .iter()
+            .map(|(headers, size, _)| Self::part_header(headers).len() as u64 + size)
+            .sum::<u64>()
+            + Self::last_part_boundary().len() as u64
+    }
 
-        // read headers as long as possible
-        let (hb, rr) = {
-            let &mut (ref mut c, ref mut reader) = self.current_part.as_mut().unwrap();
-            let b = c.read(buf).unwrap_or(0);
-            (b, reader.read(&mut buf[b..]))
-        };
+    /// Returns the boundary and headers preceding the body of a part
+    fn part_header(headers: &HeaderMap) -> Vec<u8> {
+        format!(
+            "{}--{}{}{}{}{}",
+            LINE_ENDING,
+            BOUNDARY,
+            LINE_ENDING,
+            headers
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, v.to_str().unwrap()))
+                .join(LINE_ENDING),
+            LINE_ENDING,
+            LINE_ENDING
+        )
+        .into_bytes()
+    }
 
-        match rr {
-            Ok(bytes_read) => {
-                if hb < buf.len() && bytes_read == 0 {
-                    if self.is_last_part() {
-                        // before clearing the last part, we will add the boundary that
-                        // will be written last
-                        self.last_part_boundary = Some(Cursor::new(
-                            format!("{}--{}--", LINE_ENDING, BOUNDARY).into_bytes(),
-                        ))
-                    }
-                    // We are depleted - this can trigger the next part to come in
-                    self.current_part = None;
-                }
-                let mut total_bytes_read = hb + bytes_read;
-                while total_bytes_read < buf.len() && !self.is_depleted() {
-                    match self.read(&mut buf[total_bytes_read..]) {
-                        Ok(br) => total_bytes_read += br,
-                        Err(err) => return Err(err),
-                    }
-                }
-                Ok(total_bytes_read)
-            }
-            Err(err) => {
-                // fail permanently
-                self.current_part = None;
-                self.last_part_boundary = None;
-                self.raw_parts.clear();
-                Err(err)
-            }
-        }
+    /// Returns the boundary written after the last part
+    fn last_part_boundary() -> Vec<u8> {
+        format!("{}--{}--", LINE_ENDING, BOUNDARY).into_bytes()
+    }
+
+    /// Copies as many remaining bytes of `c` as possible into `buf`, returning their amount
+    fn read_cursor(c: &mut Cursor<Vec<u8>>, buf: &mut ReadBuf<'_>) -> usize {
+        let pos = std::cmp::min(c.position() as usize, c.get_ref().len());
+        let n = std::cmp::min(c.get_ref().len() - pos, buf.remaining());
+        buf.put_slice(&c.get_ref()[pos..pos + n]);
+        c.set_position((pos + n) as u64);
+        n
     }
 }
 
+impl<'a> AsyncRead for MultiPartReader<'a> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if let Some(c) = this.last_part_boundary.as_mut() {
+                if Self::read_cursor(c, buf) == 0 {
+                    this.last_part_boundary = None;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if let Some((c, reader)) = this.current_part.as_mut() {
+                // headers go first, then the body of the part
+                if Self::read_cursor(c, buf) > 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                let filled = buf.filled().len();
+                match Pin::new(&mut **reader).poll_read(cx, buf) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(err)) => {
+                        // fail permanently
+                        this.current_part = None;
+                        this.last_part_boundary = None;
+                        this.raw_parts.clear();
+                        return Poll::Ready(Err(err));
+                    }
+                    Poll::Ready(Ok(())) if buf.filled().len() > filled => {
+                        return Poll::Ready(Ok(()))
+                    }
+                    // We are depleted - this can trigger the next part to come in
+                    Poll::Ready(Ok(())) => {
+                        this.current_part = None;
+                        if this.raw_parts.is_empty() {
+                            this.last_part_boundary = Some(Cursor::new(Self::last_part_boundary()));
+                        }
+                        continue;
+                    }
+                }
+            }
+            if this.raw_parts.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let (headers, _, reader) = this.raw_parts.remove(0);
+            this.current_part = Some((Cursor::new(Self::part_header(&headers)), reader));
+        }
+    }
+}
+
+/// The size of the chunks in which request bodies are streamed from their reader.
+const BODY_CHUNK_SIZE: usize = 1 << 16;
+
+/// Returns a request body which is fed from the given `reader`, along with the future feeding it.
+///
+/// The body is streamed in chunks of bounded size, which is why the returned future must be polled
+/// alongside the request it belongs to, i.e. `futures::join!(client.request(req), body_stream)`.
+/// Should the reader fail, the body is aborted, causing the request to fail as well.
+pub fn body_from_reader<'a, R>(
+    reader: &'a mut R,
+) -> (hyper::body::Body, impl Future<Output = ()> + 'a)
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let (mut sender, body) = hyper::body::Body::channel();
+    let body_stream = async move {
+        let mut buf = vec![0u8; BODY_CHUNK_SIZE];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    let chunk = hyper::body::Bytes::copy_from_slice(&buf[..n]);
+                    if sender.send_data(chunk).await.is_err() {
+                        // the request is gone, and we have no one to send to
+                        break;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    };
+    (body, body_stream)
+}
+
 /// The `X-Upload-Content-Type` header.
 ///
 /// Generated via rustc --pretty expanded -Z unstable-options, and manually
@@ -577,92 +2062,161 @@ impl ContentRange {
     }
 }
 
+/// The `Range` header of a `308 Resume Incomplete` response, telling which bytes the server received.
 #[derive(Clone, PartialEq, Debug)]
 pub struct RangeResponseHeader(pub Chunk);
 
 impl RangeResponseHeader {
-    fn from_bytes(raw: &[u8]) -> Self {
-        if raw.len() > 0 {
-            if let Ok(s) = std::str::from_utf8(raw) {
-                const PREFIX: &'static str = "bytes ";
-                if s.starts_with(PREFIX) {
-                    if let Ok(c) = <Chunk as FromStr>::from_str(&s[PREFIX.len()..]) {
-                        return RangeResponseHeader(c);
-                    }
-                }
-            }
-        }
+    /// Parses values like `bytes=0-42`, returning None if the value isn't understood.
+    pub fn parse(raw: &[u8]) -> Option<Self> {
+        let s = std::str::from_utf8(raw).ok()?;
+        let range = s
+            .strip_prefix("bytes=")
+            .or_else(|| s.strip_prefix("bytes "))?;
+        <Chunk as FromStr>::from_str(range)
+            .ok()
+            .map(RangeResponseHeader)
+    }
 
-        panic!(format!("Unable to parse Range header {:?}", raw))
+    /// The amount of bytes the server received, which is where the upload continues.
+    pub fn confirmed_bytes(&self) -> u64 {
+        self.0.last + 1
+    }
+}
+
+/// The state of a resumable upload, which allows to continue it later, even after a restart of the process.
+///
+/// It is handed to `Delegate::store_upload_session()` while the upload makes progress, and accepted by the
+/// `upload_resumable_from_session(...)` method of the call which started the upload.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct UploadSession {
+    /// The session URI the server assigned to the upload.
+    pub url: String,
+    /// The size of the uploaded media, in bytes.
+    pub total_size: u64,
+    /// The amount of bytes the server confirmed to have received.
+    pub confirmed_bytes: u64,
+    /// The mime type of the uploaded media.
+    pub mime_type: String,
+    /// The id of the method which started the upload, like `drive.files.create`.
+    pub method_id: String,
+}
+
+impl UploadSession {
+    /// Returns an error if the session can't be used to resume an upload of the given method with
+    /// media of the given size.
+    pub fn check(&self, method_id: &str, total_size: u64) -> Result<()> {
+        if self.method_id != method_id {
+            return Err(Error::InvalidUploadSession(format!(
+                "it was started by {}, not {}",
+                self.method_id, method_id
+            )));
+        }
+        if self.total_size != total_size {
+            return Err(Error::InvalidUploadSession(format!(
+                "it uploads {} bytes, but the media has {}",
+                self.total_size, total_size
+            )));
+        }
+        Ok(())
     }
 }
 
 /// A utility type to perform a resumable upload from start to end.
-pub struct ResumableUploadHelper<'a, A: 'a> {
-    pub client: &'a mut hyper::client::Client<
-        hyper_rustls::HttpsConnector<hyper::client::connect::HttpConnector>,
-        hyper::body::Body,
-    >,
+pub struct ResumableUploadHelper<'a, S> {
+    pub client: &'a S,
     pub delegate: &'a mut dyn Delegate,
     pub start_at: Option<u64>,
-    pub auth: &'a mut A,
     pub user_agent: &'a str,
-    pub auth_header: String,
+    pub auth_header: Option<String>,
     pub url: &'a str,
     pub reader: &'a mut dyn ReadSeek,
     pub media_type: Mime,
     pub content_length: u64,
+    pub chunk_timeout: Option<Duration>,
+    pub method_info: MethodInfo,
+    /// Sent along with each request of the upload, see `upload_headers()`.
+    pub headers: HeaderMap,
 }
 
-impl<'a, A> ResumableUploadHelper<'a, A> {
+impl<'a, S: Transport> ResumableUploadHelper<'a, S> {
+    /// The session of this upload, after the server confirmed the given amount of bytes.
+    pub fn session(&self, confirmed_bytes: u64) -> UploadSession {
+        UploadSession {
+            url: self.url.to_string(),
+            total_size: self.content_length,
+            confirmed_bytes,
+            mime_type: self.media_type.to_string(),
+            method_id: self.method_info.id.to_string(),
+        }
+    }
+
+    fn confirm(&mut self, confirmed_bytes: u64) {
+        let session = self.session(confirmed_bytes);
+        self.delegate.store_upload_session(Some(&session));
+        self.delegate
+            .upload_progress(confirmed_bytes, self.content_length);
+    }
+
     async fn query_transfer_status(
         &mut self,
-    ) -> std::result::Result<u64, hyper::Result<hyper::Response<hyper::body::Body>>> {
+    ) -> std::result::Result<u64, Result<hyper::Response<hyper::body::Body>>> {
         loop {
-            match self
+            let mut req_builder = hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri(self.url)
+                .extension(self.method_info.clone())
+                .header(USER_AGENT, self.user_agent.to_string())
+                .header(
+                    "Content-Range",
+                    ContentRange {
+                        range: None,
+                        total_length: self.content_length,
+                    }
+                    .header_value(),
+                );
+            if let Some(ref auth_header) = self.auth_header {
+                req_builder = req_builder.header(AUTHORIZATION, auth_header.clone());
+            }
+            for (name, value) in self.headers.iter() {
+                req_builder = req_builder.header(name, value.clone());
+            }
+            let request = self
                 .client
-                .request(
-                    hyper::Request::builder()
-                        .method(hyper::Method::POST)
-                        .uri(self.url)
-                        .header(USER_AGENT, self.user_agent.to_string())
-                        .header(
-                            "Content-Range",
-                            ContentRange {
-                                range: None,
-                                total_length: self.content_length,
+                .send(req_builder.body(hyper::body::Body::empty()).unwrap());
+            match Deadline::start(self.chunk_timeout).run(request).await {
+                Err(timeout) => {
+                    if let Retry::After(d) = self.delegate.timeout(timeout) {
+                        tokio::time::sleep(d).await;
+                        continue;
+                    }
+                    return Err(Err(Error::Timeout(timeout)));
+                }
+                Ok(Ok(r)) => {
+                    // 308 = resume-incomplete == PermanentRedirect, without a Range header nothing was received yet.
+                    // Any other status, like a success if the upload was completed already, is for the caller to handle.
+                    if r.status() == StatusCode::PERMANENT_REDIRECT {
+                        match r.headers().get("Range") {
+                            None => return Ok(0),
+                            Some(range) => {
+                                if let Some(h) = RangeResponseHeader::parse(range.as_bytes()) {
+                                    return Ok(h.confirmed_bytes());
+                                }
                             }
-                            .header_value(),
-                        )
-                        .header(AUTHORIZATION, self.auth_header.clone())
-                        .body(hyper::body::Body::empty())
-                        .unwrap(),
-                )
-                .await
-            {
-                Ok(r) => {
-                    // 308 = resume-incomplete == PermanentRedirect
-                    let headers = r.headers().clone();
-                    let h: RangeResponseHeader = match headers.get("Range") {
-                        Some(hh) if r.status() == StatusCode::PERMANENT_REDIRECT => {
-                            RangeResponseHeader::from_bytes(hh.as_bytes())
                         }
-                        None | Some(_) => {
-                            if let Retry::After(d) = self.delegate.http_failure(&r, None, None) {
-                                sleep(d);
-                                continue;
-                            }
-                            return Err(Ok(r));
+                        if let Retry::After(d) = self.delegate.http_failure(&r, None, None) {
+                            tokio::time::sleep(d).await;
+                            continue;
                         }
-                    };
-                    return Ok(h.0.last);
+                    }
+                    return Err(Ok(r));
                 }
-                Err(err) => {
-                    if let Retry::After(d) = self.delegate.http_error(&err) {
-                        sleep(d);
+                Ok(Err(err)) => {
+                    if let Retry::After(d) = self.delegate.http_error(&*err) {
+                        tokio::time::sleep(d).await;
                         continue;
                     }
-                    return Err(Err(err));
+                    return Err(Err(Error::HttpError(err)));
                 }
             }
         }
@@ -671,14 +2225,23 @@ impl<'a, A> ResumableUploadHelper<'a, A> {
     /// returns None if operation was cancelled by delegate, or the HttpResult.
     /// It can be that we return the result just because we didn't understand the status code -
     /// caller should check for status himself before assuming it's OK to use
-    pub async fn upload(&mut self) -> Option<hyper::Result<hyper::Response<hyper::body::Body>>> {
+    pub async fn upload(&mut self) -> Option<Result<hyper::Response<hyper::body::Body>>> {
         let mut start = match self.start_at {
             Some(s) => s,
             None => match self.query_transfer_status().await {
                 Ok(s) => s,
-                Err(result) => return Some(result),
+                Err(result) => {
+                    if let Ok(ref res) = result {
+                        // the upload completed already, or the session is gone
+                        if res.status().is_success() || res.status().is_client_error() {
+                            self.delegate.store_upload_session(None);
+                        }
+                    }
+                    return Some(result);
+                }
             },
         };
+        self.confirm(start);
 
         const MIN_CHUNK_SIZE: u64 = 1 << 18;
         let chunk_size = match self.delegate.chunk_size() {
@@ -686,16 +2249,12 @@ impl<'a, A> ResumableUploadHelper<'a, A> {
             _ => MIN_CHUNK_SIZE,
         };
 
-        self.reader.seek(SeekFrom::Start(start)).unwrap();
         loop {
             let request_size = match self.content_length - start {
                 rs if rs > chunk_size => chunk_size,
                 rs => rs,
             };
 
-            let mut section_reader = self.reader.take(request_size);
-            let mut req_bytes = vec![];
-            section_reader.read_to_end(&mut req_bytes).unwrap();
             let range_header = ContentRange {
                 range: Some(Chunk {
                     first: start,
@@ -703,64 +2262,635 @@ impl<'a, A> ResumableUploadHelper<'a, A> {
                 }),
                 total_length: self.content_length,
             };
-            start += request_size;
             if self.delegate.cancel_chunk_upload(&range_header) {
+                let session = self.session(start);
+                self.delegate.store_upload_session(Some(&session));
                 return None;
             }
-            let res = self
-                .client
-                .request(
-                    hyper::Request::builder()
-                        .method(hyper::Method::POST)
-                        .header("Content-Range", range_header.header_value())
-                        .header(CONTENT_TYPE, format!("{}", self.media_type))
-                        .header(USER_AGENT, self.user_agent.to_string())
-                        .body(hyper::body::Body::from(req_bytes))
-                        .unwrap(),
-                )
-                .await;
-            match res {
-                Ok(res) => {
+            self.reader.seek(SeekFrom::Start(start)).await.unwrap();
+            let mut section_reader = (&mut *self.reader).take(request_size);
+            let (body, body_stream) = body_from_reader(&mut section_reader);
+            let mut req_builder = hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri(self.url)
+                .extension(self.method_info.clone())
+                .header("Content-Range", range_header.header_value())
+                .header(CONTENT_TYPE, format!("{}", self.media_type))
+                .header(CONTENT_LENGTH, request_size)
+                .header(USER_AGENT, self.user_agent.to_string());
+            for (name, value) in self.headers.iter() {
+                req_builder = req_builder.header(name, value.clone());
+            }
+            let request = self.client.send(req_builder.body(body).unwrap());
+            let deadline = Deadline::start(self.chunk_timeout);
+            let res = deadline.run(async { futures::join!(request, body_stream).0 });
+            match res.await {
+                Err(timeout) => {
+                    tracing::warn!(bytes_uploaded = start, ?timeout, "chunk upload timed out");
+                    if let Retry::After(d) = self.delegate.timeout(timeout) {
+                        tokio::time::sleep(d).await;
+                        continue;
+                    }
+                    let session = self.session(start);
+                    self.delegate.store_upload_session(Some(&session));
+                    return Some(Err(Error::Timeout(timeout)));
+                }
+                Ok(Ok(res)) => {
                     if res.status() == StatusCode::PERMANENT_REDIRECT {
+                        // the server may have kept less than the whole chunk, which is then sent again
+                        start = match res.headers().get("Range") {
+                            Some(range) => match RangeResponseHeader::parse(range.as_bytes()) {
+                                Some(h) => h.confirmed_bytes(),
+                                None => start + request_size,
+                            },
+                            None => start + request_size,
+                        };
+                        tracing::debug!(
+                            bytes_uploaded = start,
+                            bytes_total = self.content_length,
+                            "uploaded chunk"
+                        );
+                        self.confirm(start);
                         continue;
                     }
 
-                    let (res_parts, res_body) = res.into_parts();
-                    let res_body_string: String = String::from_utf8(
-                        hyper::body::to_bytes(res_body)
-                            .await
-                            .unwrap()
-                            .into_iter()
-                            .collect(),
-                    )
-                    .unwrap();
-                    let reconstructed_result =
-                        hyper::Response::from_parts(res_parts, res_body_string.clone().into());
-
-                    if !reconstructed_result.status().is_success() {
+                    if !res.status().is_success() {
+                        let (res, body) = match deadline.read_body(res).await {
+                            Ok(r) => r,
+                            Err(err) => return Some(Err(err)),
+                        };
                         if let Retry::After(d) = self.delegate.http_failure(
-                            &reconstructed_result,
-                            json::from_str(&res_body_string).ok(),
-                            json::from_str(&res_body_string).ok(),
+                            &res,
+                            json::from_slice(&body).ok(),
+                            json::from_slice(&body).ok(),
                         ) {
-                            sleep(d);
+                            tokio::time::sleep(d).await;
                             continue;
                         }
+                        if res.status().is_client_error() {
+                            // the session expired or was rejected, it can't be resumed
+                            self.delegate.store_upload_session(None);
+                        }
+                        return Some(Ok(res));
+                    }
+                    self.delegate
+                        .upload_progress(self.content_length, self.content_length);
+                    self.delegate.store_upload_session(None);
+                    return Some(Ok(res));
+                }
+                Ok(Err(err)) => {
+                    tracing::warn!(bytes_uploaded = start, error = %err, "chunk upload failed");
+                    if let Retry::After(d) = self.delegate.http_error(&*err) {
+                        tokio::time::sleep(d).await;
+                        continue;
+                    }
+                    let session = self.session(start);
+                    self.delegate.store_upload_session(Some(&session));
+                    return Some(Err(Error::HttpError(err)));
+                }
+            }
+        }
+    }
+}
+
+/// A call which can be sent to the server as part of a batch request, see `BatchBuilder`.
+pub trait BatchCall {
+    /// What a successful response to the call is decoded into.
+    type Output;
+
+    /// Returns the request the call would make on its own.
+    fn batch_request(self) -> Result<BatchRequest>;
+
+    /// Decodes the successful response to the call.
+    fn batch_response(res: hyper::Response<hyper::body::Bytes>) -> Result<Self::Output>;
+}
+
+/// A single request within a batch request.
+#[derive(Clone, Debug)]
+pub struct BatchRequest {
+    pub method: Method,
+    pub url: String,
+    /// The scopes the request needs to be authorized for.
+    pub scopes: Vec<String>,
+    /// Additional headers, like `If-Match`.
+    pub headers: HeaderMap,
+    /// The json encoded request value, if there is one.
+    pub body: Option<Vec<u8>>,
+}
+
+impl BatchRequest {
+    /// Returns the request as `application/http` part of a `multipart/mixed` message.
+    fn encode(&self) -> Vec<u8> {
+        let uri = self.url.parse::<hyper::Uri>().ok();
+        let path = uri
+            .as_ref()
+            .and_then(|uri| uri.path_and_query())
+            .map(|pq| pq.as_str())
+            .unwrap_or(&self.url);
+        let mut encoded = format!("{} {} HTTP/1.1{}", self.method, path, LINE_ENDING);
+        for (name, value) in self.headers.iter() {
+            encoded += &format!("{}: {}{}", name, String::from_utf8_lossy(value.as_bytes()), LINE_ENDING);
+        }
+        if let Some(ref body) = self.body {
+            encoded += &format!(
+                "{}: application/json{}{}: {}{}",
+                CONTENT_TYPE, LINE_ENDING, CONTENT_LENGTH, body.len(), LINE_ENDING
+            );
+        }
+        encoded += LINE_ENDING;
+        let mut encoded = encoded.into_bytes();
+        if let Some(ref body) = self.body {
+            encoded.extend_from_slice(body);
+        }
+        encoded
+    }
+}
+
+/// Identifies a call added to a `BatchBuilder`, to take its result from the `BatchResponses`.
+pub struct BatchHandle<T> {
+    index: usize,
+    decode: fn(hyper::Response<hyper::body::Bytes>) -> Result<T>,
+}
+
+/// Collects calls to send them to the server as a single batch request.
+///
+/// Obtain it from the hub's `batch()` method, `add()` the calls to make, and send them with `doit()`.
+/// The result of each call is then taken from the returned `BatchResponses` using the handle `add()`
+/// returned for it.
+pub struct BatchBuilder<'a, S> {
+    client: &'a S,
+    auth: &'a dyn GetToken,
+    user_agent: &'a str,
+    headers: HeaderMap,
+    url: String,
+    requests: Vec<BatchRequest>,
+    delegate: Option<&'a mut dyn Delegate>,
+    timeout: Option<Duration>,
+}
+
+impl<'a, S: Transport> BatchBuilder<'a, S> {
+    pub fn new(
+        client: &'a S,
+        auth: &'a dyn GetToken,
+        user_agent: &'a str,
+        headers: HeaderMap,
+        url: String,
+    ) -> BatchBuilder<'a, S> {
+        BatchBuilder {
+            client,
+            auth,
+            user_agent,
+            headers,
+            url,
+            requests: Vec::new(),
+            delegate: None,
+            timeout: None,
+        }
+    }
+
+    /// Add the given call to the batch, returning the handle to take its result with.
+    ///
+    /// Fails if the call's request can't be built, e.g. due to a clashing parameter.
+    pub fn add<C: BatchCall>(&mut self, call: C) -> Result<BatchHandle<C::Output>> {
+        self.requests.push(call.batch_request()?);
+        Ok(BatchHandle {
+            index: self.requests.len() - 1,
+            decode: C::batch_response,
+        })
+    }
+
+    /// The amount of calls added so far.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// The delegate implementation is consulted whenever there is an intermediate result, or if something goes wrong
+    /// while sending the batch request, just like the delegate of a single call.
+    pub fn delegate(mut self, new_value: &'a mut dyn Delegate) -> BatchBuilder<'a, S> {
+        self.delegate = Some(new_value);
+        self
+    }
+
+    /// Set the time after which an attempt to send the batch request is abandoned.
+    /// It defaults to the timeout of the hub.
+    pub fn timeout(mut self, new_value: Duration) -> BatchBuilder<'a, S> {
+        self.timeout = Some(new_value);
+        self
+    }
+
+    /// Send all calls in a single request, and return their responses.
+    ///
+    /// Fails only if the batch request itself fails. The result of each call is obtained from the
+    /// returned `BatchResponses`.
+    pub async fn doit(mut self) -> Result<BatchResponses> {
+        let span = call_span("batch", &Method::POST, &self.url);
+        self._doit().instrument(span).await
+    }
+
+    async fn _doit(&mut self) -> Result<BatchResponses> {
+        let mut dd = DefaultDelegate;
+        let dlg: &mut dyn Delegate = match self.delegate.take() {
+            Some(d) => d,
+            None => &mut dd,
+        };
+        dlg.begin(MethodInfo {
+            id: "batch",
+            http_method: Method::POST,
+        });
+        let mut trace = CallTrace::current();
+        let scopes = self
+            .requests
+            .iter()
+            .flat_map(|r| r.scopes.iter().map(String::as_str))
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let parts = self.requests.iter().map(BatchRequest::encode).collect::<Vec<_>>();
+
+        loop {
+            let token = if scopes.is_empty() {
+                None
+            } else {
+                match self.auth.get_token(&scopes).await {
+                    Ok(token) => token,
+                    Err(err) => match dlg.token(&*err) {
+                        Some(token) => Some(token),
+                        None => {
+                            dlg.finished(false);
+                            return Err(Error::MissingToken(err));
+                        }
+                    },
+                }
+            };
+
+            let mut readers = parts.iter().map(|p| &p[..]).collect::<Vec<_>>();
+            let mut mp_reader = MultiPartReader::mixed();
+            mp_reader.reserve_exact(readers.len());
+            for (index, reader) in readers.iter_mut().enumerate() {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static("application/http"),
+                );
+                headers.insert(
+                    "Content-ID",
+                    hyper::header::HeaderValue::from_str(&format!("<{}>", index + 1)).unwrap(),
+                );
+                let size = reader.len() as u64;
+                mp_reader.add_part_with_headers(reader, size, headers);
+            }
+            let content_type = format!("{}", mp_reader.mime_type());
+            let content_length = mp_reader.content_length();
+            let (body, body_stream) = body_from_reader(&mut mp_reader);
+
+            dlg.pre_request();
+            trace.attempt(&self.url, content_length);
+            let mut req_builder = hyper::Request::builder()
+                .method(Method::POST)
+                .uri(self.url.as_str())
+                .header(USER_AGENT, self.user_agent.to_string())
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_LENGTH, content_length);
+            if let Some(ref token) = token {
+                req_builder = req_builder.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            for (name, value) in self.headers.iter() {
+                req_builder = req_builder.header(name, value.clone());
+            }
+            let request = self.client.send(req_builder.body(body).unwrap());
+
+            let deadline = Deadline::start(self.timeout);
+            let res = deadline.run(async { futures::join!(request, body_stream).0 });
+            match res.await {
+                Err(timeout) => {
+                    trace.timeout(timeout);
+                    if let Retry::After(d) = dlg.timeout(timeout) {
+                        tokio::time::sleep(d).await;
+                        continue;
                     }
-                    return Some(Ok(reconstructed_result));
+                    dlg.finished(false);
+                    return Err(Error::Timeout(timeout));
                 }
-                Err(err) => {
-                    if let Retry::After(d) = self.delegate.http_error(&err) {
-                        sleep(d);
+                Ok(Err(err)) => {
+                    trace.error(&*err);
+                    if let Retry::After(d) = dlg.http_error(&*err) {
+                        tokio::time::sleep(d).await;
                         continue;
                     }
-                    return Some(Err(err));
+                    dlg.finished(false);
+                    return Err(Error::HttpError(err));
+                }
+                Ok(Ok(res)) => {
+                    trace.response(&res);
+                    let (res, res_body) = match deadline.read_body(res).await {
+                        Ok(r) => r,
+                        Err(err) => {
+                            dlg.finished(false);
+                            return Err(err);
+                        }
+                    };
+                    trace.received(res_body.len());
+                    if !res.status().is_success() {
+                        let json_server_error = json::from_slice::<JsonServerError>(&res_body).ok();
+                        let api_error = ApiError::from_body(&res_body);
+                        if let Retry::After(d) = dlg.http_failure(&res, json_server_error, api_error.clone()) {
+                            tokio::time::sleep(d).await;
+                            continue;
+                        }
+                        dlg.finished(false);
+                        return match api_error {
+                            None => Err(Error::Failure(res)),
+                            Some(err) => Err(Error::Api(err)),
+                        };
+                    }
+                    let content_type = res
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or("");
+                    let responses = match parse_batch_response(content_type, &res_body, parts.len()) {
+                        Ok(responses) => responses,
+                        Err(err) => {
+                            dlg.finished(false);
+                            return Err(err);
+                        }
+                    };
+                    dlg.finished(true);
+                    return Ok(BatchResponses { responses });
                 }
             }
         }
     }
 }
 
+/// The responses to the calls of a batch request.
+pub struct BatchResponses {
+    responses: Vec<Option<hyper::Response<hyper::body::Bytes>>>,
+}
+
+impl BatchResponses {
+    /// Take the result of the call identified by `handle`.
+    ///
+    /// Failed calls produce the same errors they would if they were made on their own.
+    pub fn take<T>(&mut self, handle: BatchHandle<T>) -> Result<T> {
+        let res = self
+            .responses
+            .get_mut(handle.index)
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                Error::BatchResponse(format!("missing response to call {}", handle.index + 1))
+            })?;
+        if res.status() == hyper::StatusCode::NOT_MODIFIED {
+            let (parts, body) = res.into_parts();
+            return Err(Error::NotModified(hyper::Response::from_parts(
+                parts,
+                hyper::body::Body::from(body),
+            )));
+        }
+        if !res.status().is_success() {
+            let (parts, body) = res.into_parts();
+            return match ApiError::from_body(&body) {
+                None => Err(Error::Failure(hyper::Response::from_parts(
+                    parts,
+                    hyper::body::Body::from(body),
+                ))),
+                Some(err) => Err(Error::Api(err)),
+            };
+        }
+        (handle.decode)(res)
+    }
+}
+
+/// Decodes the json body of a successful response, as used by `BatchCall::batch_response()`.
+pub fn decode_json_response<T: ::serde::de::DeserializeOwned>(
+    res: hyper::Response<hyper::body::Bytes>,
+) -> Result<(hyper::Response<hyper::body::Body>, T)> {
+    let (parts, body) = res.into_parts();
+    match json::from_slice(&body) {
+        Ok(decoded) => Ok((
+            hyper::Response::from_parts(parts, hyper::body::Body::from(body)),
+            decoded,
+        )),
+        Err(err) => Err(Error::JsonDecodeError(
+            String::from_utf8_lossy(&body).into_owned(),
+            err,
+        )),
+    }
+}
+
+/// Returns the position of `needle` in `haystack`, if it is contained.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Splits `data` into the part before the first empty line, and the part after it.
+fn split_headers(data: &[u8]) -> Option<(&str, &[u8])> {
+    let (pos, len) = match (find_bytes(data, b"\r\n\r\n"), find_bytes(data, b"\n\n")) {
+        (Some(crlf), Some(lf)) if lf < crlf => (lf, 2),
+        (Some(crlf), _) => (crlf, 4),
+        (None, Some(lf)) => (lf, 2),
+        (None, None) => return None,
+    };
+    let head = std::str::from_utf8(&data[..pos]).ok()?;
+    Some((head, &data[pos + len..]))
+}
+
+/// Parses the `multipart/mixed` response to a batch request of `count` calls, returning the
+/// responses in the order of their calls.
+///
+/// Each part is matched to its call by its `Content-ID`, which is `<response-N>` for the N-th call.
+/// Parts without one are assumed to be in the order of the calls.
+pub fn parse_batch_response(
+    content_type: &str,
+    body: &[u8],
+    count: usize,
+) -> Result<Vec<Option<hyper::Response<hyper::body::Bytes>>>> {
+    let boundary = content_type
+        .split(';')
+        .filter_map(|p| p.trim().strip_prefix("boundary="))
+        .next()
+        .map(|b| b.trim_matches('"'))
+        .ok_or_else(|| Error::BatchResponse(format!("no boundary in '{}'", content_type)))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut responses: Vec<Option<hyper::Response<hyper::body::Bytes>>> = (0..count).map(|_| None).collect();
+    let mut rest = match find_bytes(body, &delimiter) {
+        Some(pos) => &body[pos + delimiter.len()..],
+        None => return Err(Error::BatchResponse("no parts found".to_string())),
+    };
+    let mut position = 0;
+    while !rest.starts_with(b"--") {
+        let end = find_bytes(rest, &delimiter)
+            .ok_or_else(|| Error::BatchResponse("unterminated part".to_string()))?;
+        let part = &rest[..end];
+        rest = &rest[end + delimiter.len()..];
+
+        let part = part.strip_prefix(b"\r\n").or_else(|| part.strip_prefix(b"\n")).unwrap_or(part);
+        let (part_headers, http) = split_headers(part)
+            .ok_or_else(|| Error::BatchResponse("part without headers".to_string()))?;
+        let index = part_headers
+            .lines()
+            .filter_map(|l| l.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-ID"))
+            .and_then(|(_, value)| {
+                let value = value.trim().trim_start_matches('<').trim_end_matches('>');
+                value.strip_prefix("response-")?.parse::<usize>().ok()
+            })
+            .and_then(|id| id.checked_sub(1))
+            .unwrap_or(position);
+        position += 1;
+
+        let (head, res_body) = split_headers(http)
+            .ok_or_else(|| Error::BatchResponse("part without http response".to_string()))?;
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .and_then(|l| l.split_whitespace().nth(1))
+            .and_then(|s| s.parse::<StatusCode>().ok())
+            .ok_or_else(|| Error::BatchResponse(format!("invalid status line in '{}'", head)))?;
+        let mut res = hyper::Response::builder().status(status);
+        for (name, value) in lines.filter_map(|l| l.split_once(':')) {
+            res = res.header(name.trim(), value.trim());
+        }
+        let res_body = res_body
+            .strip_suffix(b"\r\n")
+            .or_else(|| res_body.strip_suffix(b"\n"))
+            .unwrap_or(res_body);
+        let res = res
+            .body(hyper::body::Bytes::copy_from_slice(res_body))
+            .map_err(|err| Error::BatchResponse(err.to_string()))?;
+        if let Some(slot) = responses.get_mut(index) {
+            *slot = Some(res);
+        }
+    }
+    Ok(responses)
+}
+
+/// Reads the entire body of the given response into memory.
+///
+/// Returns the bytes along with a response whose body can still be read by the caller,
+/// which is useful when the body of a failed request is needed for error reporting.
+pub async fn read_body(
+    res: hyper::Response<hyper::body::Body>,
+) -> hyper::Result<(hyper::Response<hyper::body::Body>, hyper::body::Bytes)> {
+    let (parts, body) = res.into_parts();
+    let bytes = hyper::body::to_bytes(body).await?;
+    Ok((
+        hyper::Response::from_parts(parts, hyper::body::Body::from(bytes.clone())),
+        bytes,
+    ))
+}
+
+/// Configures gzip compression of the bodies exchanged with the server, which is disabled by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Compression {
+    /// Ask the server to compress json responses, which are decompressed transparently.
+    /// Media downloads are not affected.
+    pub responses: bool,
+    /// Compress json request bodies of at least the given amount of bytes.
+    /// Please note that not all APIs accept compressed requests.
+    pub min_request_size: Option<usize>,
+}
+
+impl Compression {
+    /// Returns the gzip compressed `body` if it is large enough to be compressed.
+    pub fn compress_request(&self, body: &[u8]) -> Option<Vec<u8>> {
+        match self.min_request_size {
+            Some(min_size) if body.len() >= min_size => Some(gzip(body)),
+            _ => None,
+        }
+    }
+}
+
+/// Compresses the given data using gzip.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .expect("writing to memory to work")
+}
+
+/// Asks the server for a gzip compressed response. Google APIs only compress responses if the user agent
+/// mentions gzip as well, which is why it is amended.
+pub fn accept_gzip(mut req_builder: hyper::http::request::Builder) -> hyper::http::request::Builder {
+    if let Some(headers) = req_builder.headers_mut() {
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|ua| ua.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        if !user_agent.contains("gzip") {
+            let user_agent = format!("{} (gzip)", user_agent);
+            if let Ok(value) = HeaderValue::from_str(user_agent.trim()) {
+                headers.insert(USER_AGENT, value);
+            }
+        }
+    }
+    req_builder
+}
+
+/// Decompresses the body of the given response if it is gzip encoded, removing the `Content-Encoding`.
+fn decode_body(
+    res: hyper::Response<hyper::body::Body>,
+    bytes: hyper::body::Bytes,
+) -> io::Result<(hyper::Response<hyper::body::Body>, hyper::body::Bytes)> {
+    let is_gzip = res
+        .headers()
+        .get(CONTENT_ENCODING)
+        .map_or(false, |encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+    if !is_gzip {
+        return Ok((res, bytes));
+    }
+    let mut decoder = flate2::write::GzDecoder::new(Vec::new());
+    decoder.write_all(&bytes)?;
+    let bytes = hyper::body::Bytes::from(decoder.finish()?);
+    let (mut parts, _) = res.into_parts();
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Ok((
+        hyper::Response::from_parts(parts, hyper::body::Body::from(bytes.clone())),
+        bytes,
+    ))
+}
+
+/// The point in time by which an attempt to make a request has to be finished, derived from a timeout.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Option<(tokio::time::Instant, Duration)>);
+
+impl Deadline {
+    /// Start counting down the given timeout. There is no deadline if it is `None`.
+    pub fn start(timeout: Option<Duration>) -> Deadline {
+        Deadline(timeout.map(|t| (tokio::time::Instant::now() + t, t)))
+    }
+
+    /// Await the given future, or fail with the timeout once the deadline passed.
+    pub async fn run<F: Future>(&self, future: F) -> std::result::Result<F::Output, Duration> {
+        match self.0 {
+            Some((at, timeout)) => tokio::time::timeout_at(at, future)
+                .await
+                .map_err(|_| timeout),
+            None => Ok(future.await),
+        }
+    }
+
+    /// Like `read_body()`, but fails with `Error::Timeout` once the deadline passed.
+    /// Gzip encoded bodies are decompressed.
+    pub async fn read_body(
+        &self,
+        res: hyper::Response<hyper::body::Body>,
+    ) -> Result<(hyper::Response<hyper::body::Body>, hyper::body::Bytes)> {
+        match self.run(read_body(res)).await {
+            Ok(Ok((res, bytes))) => {
+                decode_body(res, bytes).map_err(|err| Error::HttpError(Box::new(err)))
+            }
+            Ok(Err(err)) => Err(Error::HttpError(err.into())),
+            Err(timeout) => Err(Error::Timeout(timeout)),
+        }
+    }
+}
+
 // Copy of src/rust/cli/client.rs
 // TODO(ST): Allow sharing common code between program types
 pub fn remove_json_null_values(value: &mut json::value::Value) {
@@ -794,3 +2924,1043 @@ pub fn remove_json_null_values(value: &mut json::value::Value) {
         _ => {}
     }
 }
+
+/// Serialization helpers for values which Google APIs transmit as strings in JSON, like 64-bit integers,
+/// bytes and durations.
+///
+/// Generated schema types use them through `#[serde(with = "client::serde::As::<...>")]`, where the type
+/// parameter describes how the field is encoded, e.g. `Option<client::serde::Str>` for an `Option<i64>`.
+pub mod serde {
+    use std::collections::HashMap;
+    use std::fmt::{self, Display};
+    use std::hash::Hash;
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    use ::serde::de::{self, Deserialize, Deserializer, Visitor};
+    use ::serde::ser::{Serialize, Serializer};
+
+    /// Serializes a value of type `T` in the way described by `Self`.
+    pub trait SerializeAs<T> {
+        fn serialize_as<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error>;
+    }
+
+    /// Deserializes a value of type `T` in the way described by `Self`.
+    pub trait DeserializeAs<'de, T> {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error>;
+    }
+
+    /// Adapts the `SerializeAs` and `DeserializeAs` implementations of `T` for use with `#[serde(with = "...")]`.
+    pub struct As<T: ?Sized>(PhantomData<T>);
+
+    impl<T: ?Sized> As<T> {
+        pub fn serialize<S, I>(value: &I, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+            T: SerializeAs<I>,
+        {
+            T::serialize_as(value, serializer)
+        }
+
+        pub fn deserialize<'de, D, I>(deserializer: D) -> Result<I, D::Error>
+        where
+            D: Deserializer<'de>,
+            T: DeserializeAs<'de, I>,
+        {
+            T::deserialize_as(deserializer)
+        }
+    }
+
+    struct SerializeAsWrap<'a, T, U>(&'a T, PhantomData<U>);
+
+    impl<'a, T, U: SerializeAs<T>> Serialize for SerializeAsWrap<'a, T, U> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            U::serialize_as(self.0, serializer)
+        }
+    }
+
+    struct DeserializeAsWrap<T, U>(T, PhantomData<U>);
+
+    impl<'de, T, U: DeserializeAs<'de, T>> Deserialize<'de> for DeserializeAsWrap<T, U> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            U::deserialize_as(deserializer).map(|v| DeserializeAsWrap(v, PhantomData))
+        }
+    }
+
+    impl<T, U: SerializeAs<T>> SerializeAs<Option<T>> for Option<U> {
+        fn serialize_as<S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+            match *value {
+                Some(ref v) => serializer.serialize_some(&SerializeAsWrap::<T, U>(v, PhantomData)),
+                None => serializer.serialize_none(),
+            }
+        }
+    }
+
+    impl<'de, T, U: DeserializeAs<'de, T>> DeserializeAs<'de, Option<T>> for Option<U> {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+            Ok(Option::<DeserializeAsWrap<T, U>>::deserialize(deserializer)?.map(|v| v.0))
+        }
+    }
+
+    impl<T, U: SerializeAs<T>> SerializeAs<Vec<T>> for Vec<U> {
+        fn serialize_as<S: Serializer>(value: &Vec<T>, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(value.iter().map(|v| SerializeAsWrap::<T, U>(v, PhantomData)))
+        }
+    }
+
+    impl<'de, T, U: DeserializeAs<'de, T>> DeserializeAs<'de, Vec<T>> for Vec<U> {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+            Ok(Vec::<DeserializeAsWrap<T, U>>::deserialize(deserializer)?
+                .into_iter()
+                .map(|v| v.0)
+                .collect())
+        }
+    }
+
+    impl<K, T, U> SerializeAs<HashMap<K, T>> for HashMap<K, U>
+    where
+        K: Serialize,
+        U: SerializeAs<T>,
+    {
+        fn serialize_as<S: Serializer>(value: &HashMap<K, T>, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(
+                value
+                    .iter()
+                    .map(|(k, v)| (k, SerializeAsWrap::<T, U>(v, PhantomData))),
+            )
+        }
+    }
+
+    impl<'de, K, T, U> DeserializeAs<'de, HashMap<K, T>> for HashMap<K, U>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        U: DeserializeAs<'de, T>,
+    {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<K, T>, D::Error> {
+            Ok(HashMap::<K, DeserializeAsWrap<T, U>>::deserialize(deserializer)?
+                .into_iter()
+                .map(|(k, v)| (k, v.0))
+                .collect())
+        }
+    }
+
+    /// Encodes values as strings using their `Display` implementation, and decodes them using `FromStr`.
+    /// Numbers are accepted as well when decoding, as not all APIs quote 64-bit integers consistently.
+    pub struct Str;
+
+    impl<T: Display> SerializeAs<T> for Str {
+        fn serialize_as<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(value)
+        }
+    }
+
+    impl<'de, T> DeserializeAs<'de, T> for Str
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+            struct StrVisitor<T>(PhantomData<T>);
+
+            impl<'de, T> Visitor<'de> for StrVisitor<T>
+            where
+                T: FromStr,
+                T::Err: Display,
+            {
+                type Value = T;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a string or a number")
+                }
+
+                fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+                    v.parse().map_err(E::custom)
+                }
+
+                fn visit_i64<E: de::Error>(self, v: i64) -> Result<T, E> {
+                    self.visit_str(&v.to_string())
+                }
+
+                fn visit_u64<E: de::Error>(self, v: u64) -> Result<T, E> {
+                    self.visit_str(&v.to_string())
+                }
+
+                fn visit_f64<E: de::Error>(self, v: f64) -> Result<T, E> {
+                    self.visit_str(&v.to_string())
+                }
+            }
+
+            deserializer.deserialize_any(StrVisitor(PhantomData))
+        }
+    }
+
+    /// Encodes bytes as standard base64 with padding, and decodes them from either the standard or
+    /// the URL-safe alphabet, with or without padding.
+    pub struct Base64;
+
+    impl SerializeAs<Vec<u8>> for Base64 {
+        fn serialize_as<S: Serializer>(value: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&base64::encode(value))
+        }
+    }
+
+    impl<'de> DeserializeAs<'de, Vec<u8>> for Base64 {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            let s = String::deserialize(deserializer)?;
+            base64::decode_config(&s, base64::STANDARD)
+                .or_else(|_| base64::decode_config(&s, base64::URL_SAFE))
+                .map_err(de::Error::custom)
+        }
+    }
+
+    /// Encodes durations like `"3.5s"`, the JSON representation of `google.protobuf.Duration`.
+    pub struct Duration;
+
+    /// Parses a duration like `"3.5s"` or `"-0.000001s"`.
+    pub fn parse_duration(value: &str) -> Option<chrono::Duration> {
+        let value = value.strip_suffix('s')?;
+        let (negative, value) = match value.strip_prefix('-') {
+            Some(v) => (true, v),
+            None => (false, value),
+        };
+        let (seconds, fraction) = match value.find('.') {
+            Some(pos) => (&value[..pos], &value[pos + 1..]),
+            None => (value, ""),
+        };
+        if seconds.is_empty()
+            || (value.contains('.') && fraction.is_empty())
+            || fraction.len() > 9
+            || !seconds.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let seconds: i64 = seconds.parse().ok()?;
+        let nanos: i64 = if fraction.is_empty() {
+            0
+        } else {
+            format!("{:0<9}", fraction).parse().ok()?
+        };
+        // chrono::Duration::seconds() panics beyond what its milliseconds can hold
+        if seconds > i64::MAX / 1000 {
+            return None;
+        }
+        let duration =
+            chrono::Duration::seconds(seconds).checked_add(&chrono::Duration::nanoseconds(nanos))?;
+        Some(if negative { -duration } else { duration })
+    }
+
+    /// Formats a duration the way `parse_duration()` expects it.
+    pub fn format_duration(value: &chrono::Duration) -> String {
+        let sign = if *value < chrono::Duration::zero() { "-" } else { "" };
+        let abs = if sign.is_empty() { *value } else { -*value };
+        let seconds = abs.num_seconds();
+        let nanos = (abs - chrono::Duration::seconds(seconds))
+            .num_nanoseconds()
+            .unwrap_or(0);
+        if nanos == 0 {
+            format!("{}{}s", sign, seconds)
+        } else {
+            let fraction = format!("{:09}", nanos);
+            format!("{}{}.{}s", sign, seconds, fraction.trim_end_matches('0'))
+        }
+    }
+
+    impl SerializeAs<chrono::Duration> for Duration {
+        fn serialize_as<S: Serializer>(value: &chrono::Duration, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&format_duration(value))
+        }
+    }
+
+    impl<'de> DeserializeAs<'de, chrono::Duration> for Duration {
+        fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<chrono::Duration, D::Error> {
+            let s = String::deserialize(deserializer)?;
+            parse_duration(&s)
+                .ok_or_else(|| de::Error::custom(format!("invalid duration: {:?}", s)))
+        }
+    }
+}
+
+/// Application Default Credentials, i.e. finding credentials the way all Google client libraries do.
+///
+/// `default_credentials()` looks for them, in this order,
+///
+/// * in the file named by the `GOOGLE_APPLICATION_CREDENTIALS` environment variable, which holds either the key
+///   of a service account or the refresh token of a user,
+/// * in the well-known file written by `gcloud auth application-default login`,
+/// * at the metadata server of the Google Cloud environment the program runs in.
+///
+/// The `DefaultCredentials` it returns can be passed to the constructor of any hub.
+pub mod auth {
+    use std::collections::HashMap;
+    use std::error;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use hyper::header::{HeaderValue, CONTENT_TYPE};
+    use serde_json as json;
+
+    use super::{GetToken, GetTokenError, GetTokenFuture};
+
+    /// The environment variable naming the credentials file.
+    pub const CREDENTIALS_ENV: &'static str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+    /// The environment variable holding the host of the metadata server, to use instead of the default one.
+    pub const METADATA_HOST_ENV: &'static str = "GCE_METADATA_HOST";
+
+    const DEFAULT_METADATA_SERVER: &'static str = "http://169.254.169.254";
+    const DEFAULT_TOKEN_URI: &'static str = "https://oauth2.googleapis.com/token";
+    const METADATA_FLAVOR: &'static str = "Metadata-Flavor";
+    const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+    // tokens are refreshed a little before they expire, to not use them while they run out
+    const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+    type HttpClient = hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+    /// Obtain a token source following the Application Default Credentials chain, see the module documentation.
+    pub async fn default_credentials() -> Result<DefaultCredentials, GetTokenError> {
+        DefaultCredentialsBuilder::default().build().await
+    }
+
+    /// Where `DefaultCredentials` were found.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum CredentialsSource {
+        /// The file named by `GOOGLE_APPLICATION_CREDENTIALS`, or passed to `credentials_file()`.
+        File(PathBuf),
+        /// The well-known file written by `gcloud`.
+        WellKnownFile(PathBuf),
+        /// The metadata server with the given URL.
+        MetadataServer(String),
+    }
+
+    /// Configures where `DefaultCredentials` are looked for, which is useful to test against a local stand-in
+    /// for the metadata server, or to impersonate users using domain-wide delegation.
+    #[derive(Clone, Debug, Default)]
+    pub struct DefaultCredentialsBuilder {
+        credentials_file: Option<PathBuf>,
+        well_known_file: Option<PathBuf>,
+        metadata_server: Option<String>,
+        subject: Option<String>,
+        ignore_environment: bool,
+    }
+
+    impl DefaultCredentialsBuilder {
+        /// Use the given credentials file instead of the one named by `GOOGLE_APPLICATION_CREDENTIALS`.
+        pub fn credentials_file<P: Into<PathBuf>>(mut self, path: P) -> DefaultCredentialsBuilder {
+            self.credentials_file = Some(path.into());
+            self
+        }
+
+        /// Look for the well-known file of `gcloud` at the given path instead of its default location.
+        pub fn well_known_file<P: Into<PathBuf>>(mut self, path: P) -> DefaultCredentialsBuilder {
+            self.well_known_file = Some(path.into());
+            self
+        }
+
+        /// Ask the metadata server at the given URL, like `http://localhost:8080`, instead of the default one.
+        pub fn metadata_server<U: Into<String>>(mut self, url: U) -> DefaultCredentialsBuilder {
+            self.metadata_server = Some(url.into());
+            self
+        }
+
+        /// Act on behalf of the given user, using domain-wide delegation.
+        /// This requires the credentials to be the key of a service account.
+        pub fn subject<S: Into<String>>(mut self, subject: S) -> DefaultCredentialsBuilder {
+            self.subject = Some(subject.into());
+            self
+        }
+
+        /// Ignore the environment variables which tell where to look for credentials, i.e. `GOOGLE_APPLICATION_CREDENTIALS`,
+        /// `GCE_METADATA_HOST`, and the ones locating the well-known file of `gcloud`. Only the locations set
+        /// using the other methods, and the default metadata server are used then.
+        pub fn ignore_environment(mut self) -> DefaultCredentialsBuilder {
+            self.ignore_environment = true;
+            self
+        }
+
+        /// Look for credentials, failing if there are none or they can't be used.
+        pub async fn build(self) -> Result<DefaultCredentials, GetTokenError> {
+            let mut config = rustls::ClientConfig::new();
+            config.root_store = super::native_root_store()
+                .map_err(|err| format!("could not load the root certificates of the system: {}", err))?;
+            let http: HttpClient = hyper::Client::builder().build(super::https_connector(config));
+            let credentials_file = self
+                .credentials_file
+                .clone()
+                .or_else(|| self.env_var(CREDENTIALS_ENV).map(PathBuf::from));
+            if let Some(path) = credentials_file {
+                let token = self.from_file(&http, &path).await?;
+                return Ok(DefaultCredentials {
+                    source: CredentialsSource::File(path),
+                    token,
+                });
+            }
+            let well_known_file = match self.well_known_file {
+                Some(ref path) => Some(path.clone()),
+                None if self.ignore_environment => None,
+                None => well_known_file(),
+            };
+            if let Some(path) = well_known_file {
+                if path.is_file() {
+                    let token = self.from_file(&http, &path).await?;
+                    return Ok(DefaultCredentials {
+                        source: CredentialsSource::WellKnownFile(path),
+                        token,
+                    });
+                }
+            }
+            let server = self
+                .metadata_server
+                .clone()
+                .or_else(|| {
+                    self.env_var(METADATA_HOST_ENV)
+                        .and_then(|host| host.into_string().ok())
+                        .map(|host| format!("http://{}", host))
+                })
+                .unwrap_or_else(|| DEFAULT_METADATA_SERVER.to_string());
+            let server = server.trim_end_matches('/').to_string();
+            if self.subject.is_some() {
+                return Err(subject_unsupported("the metadata server"));
+            }
+            if !is_metadata_server(&http, &server).await {
+                return Err("could not find default credentials, neither in a file nor at the metadata server".into());
+            }
+            Ok(DefaultCredentials {
+                source: CredentialsSource::MetadataServer(server.clone()),
+                token: Box::new(MetadataServer {
+                    http,
+                    server,
+                    cache: Default::default(),
+                }),
+            })
+        }
+
+        fn env_var(&self, name: &str) -> Option<std::ffi::OsString> {
+            if self.ignore_environment {
+                None
+            } else {
+                std::env::var_os(name)
+            }
+        }
+
+        async fn from_file(
+            &self,
+            http: &HttpClient,
+            path: &PathBuf,
+        ) -> Result<Box<dyn GetToken>, GetTokenError> {
+            let contents = tokio::fs::read(path)
+                .await
+                .map_err(|err| format!("could not read credentials file {}: {}", path.display(), err))?;
+            let kind: CredentialsKind = json::from_slice(&contents)?;
+            match kind.kind.as_str() {
+                "service_account" => {
+                    let key = oauth2::parse_service_account_key(&contents)?;
+                    let mut builder = oauth2::ServiceAccountAuthenticator::builder(key);
+                    if let Some(ref subject) = self.subject {
+                        builder = builder.subject(subject.clone());
+                    }
+                    Ok(Box::new(builder.build().await?))
+                }
+                "authorized_user" => {
+                    if self.subject.is_some() {
+                        return Err(subject_unsupported("the credentials of a user"));
+                    }
+                    let mut user: AuthorizedUser = json::from_slice(&contents)?;
+                    user.token_uri = user.token_uri.or_else(|| Some(DEFAULT_TOKEN_URI.to_string()));
+                    Ok(Box::new(AuthorizedUserToken {
+                        http: http.clone(),
+                        user,
+                        cache: Default::default(),
+                    }))
+                }
+                other => Err(format!("unsupported type of credentials '{}' in {}", other, path.display()).into()),
+            }
+        }
+    }
+
+    /// A token source found by `default_credentials()`. It can be passed to the constructor of any hub.
+    pub struct DefaultCredentials {
+        source: CredentialsSource,
+        token: Box<dyn GetToken>,
+    }
+
+    impl DefaultCredentials {
+        /// Where the credentials were found.
+        pub fn source(&self) -> &CredentialsSource {
+            &self.source
+        }
+    }
+
+    impl GetToken for DefaultCredentials {
+        fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a> {
+            self.token.get_token(scopes)
+        }
+    }
+
+    fn subject_unsupported(what: &str) -> GetTokenError {
+        format!("domain-wide delegation requires the key of a service account, not {}", what).into()
+    }
+
+    /// The location of the file written by `gcloud auth application-default login`.
+    fn well_known_file() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("CLOUDSDK_CONFIG") {
+            Some(dir) => PathBuf::from(dir),
+            None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?).join("gcloud"),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config").join("gcloud"),
+        };
+        Some(config_dir.join("application_default_credentials.json"))
+    }
+
+    async fn is_metadata_server(http: &HttpClient, server: &str) -> bool {
+        let req = match hyper::Request::get(format!("{}/computeMetadata/v1/", server))
+            .header(METADATA_FLAVOR, "Google")
+            .body(hyper::body::Body::empty())
+        {
+            Ok(req) => req,
+            Err(_) => return false,
+        };
+        match tokio::time::timeout(METADATA_PROBE_TIMEOUT, http.request(req)).await {
+            Ok(Ok(res)) => res
+                .headers()
+                .get(METADATA_FLAVOR)
+                .map_or(false, |flavor| flavor == "Google"),
+            _ => false,
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct CredentialsKind {
+        #[serde(rename = "type")]
+        kind: String,
+    }
+
+    #[derive(Deserialize)]
+    struct AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+        #[serde(default)]
+        token_uri: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        access_token: String,
+        #[serde(default)]
+        expires_in: Option<u64>,
+    }
+
+    /// Tokens by the scopes they were obtained for, until shortly before they expire.
+    #[derive(Default)]
+    struct TokenCache(Mutex<HashMap<Vec<String>, (String, Option<Instant>)>>);
+
+    impl TokenCache {
+        fn get(&self, scopes: &[String]) -> Option<String> {
+            let cache = self.0.lock().unwrap();
+            match cache.get(scopes) {
+                Some((token, expires_at)) if expires_at.map_or(true, |at| Instant::now() < at) => {
+                    Some(token.clone())
+                }
+                _ => None,
+            }
+        }
+
+        fn put(&self, scopes: Vec<String>, res: &TokenResponse) {
+            let expires_at = res.expires_in.map(|secs| {
+                Instant::now() + Duration::from_secs(secs).checked_sub(EXPIRY_MARGIN).unwrap_or_default()
+            });
+            self.0
+                .lock()
+                .unwrap()
+                .insert(scopes, (res.access_token.clone(), expires_at));
+        }
+    }
+
+    async fn request_token(
+        http: &HttpClient,
+        req: hyper::Request<hyper::body::Body>,
+    ) -> Result<TokenResponse, GetTokenError> {
+        let res = http.request(req).await?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        if !status.is_success() {
+            return Err(format!(
+                "token request failed with status {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into());
+        }
+        Ok(json::from_slice(&body)?)
+    }
+
+    /// Obtains tokens for the default service account of the environment from its metadata server.
+    struct MetadataServer {
+        http: HttpClient,
+        server: String,
+        cache: TokenCache,
+    }
+
+    impl GetToken for MetadataServer {
+        fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a> {
+            Box::pin(async move {
+                let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+                if let Some(token) = self.cache.get(&scopes) {
+                    return Ok(Some(token));
+                }
+                let mut url = format!("{}/computeMetadata/v1/instance/service-accounts/default/token", self.server);
+                if !scopes.is_empty() {
+                    let query = url::form_urlencoded::Serializer::new(String::new())
+                        .append_pair("scopes", &scopes.join(","))
+                        .finish();
+                    url = format!("{}?{}", url, query);
+                }
+                let req = hyper::Request::get(url)
+                    .header(METADATA_FLAVOR, "Google")
+                    .body(hyper::body::Body::empty())
+                    .map_err(|err| Box::new(err) as Box<dyn error::Error + Send + Sync>)?;
+                let res = request_token(&self.http, req).await?;
+                self.cache.put(scopes, &res);
+                Ok(Some(res.access_token))
+            })
+        }
+    }
+
+    /// Obtains tokens for a user using the refresh token `gcloud` stored for them.
+    /// The scopes are the ones the user agreed to, the ones requested are ignored.
+    struct AuthorizedUserToken {
+        http: HttpClient,
+        user: AuthorizedUser,
+        cache: TokenCache,
+    }
+
+    impl GetToken for AuthorizedUserToken {
+        fn get_token<'a>(&'a self, _scopes: &'a [&str]) -> GetTokenFuture<'a> {
+            Box::pin(async move {
+                if let Some(token) = self.cache.get(&[]) {
+                    return Ok(Some(token));
+                }
+                let body = url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("grant_type", "refresh_token")
+                    .append_pair("client_id", &self.user.client_id)
+                    .append_pair("client_secret", &self.user.client_secret)
+                    .append_pair("refresh_token", &self.user.refresh_token)
+                    .finish();
+                let req = hyper::Request::post(self.user.token_uri.as_deref().unwrap_or(DEFAULT_TOKEN_URI))
+                    .header(
+                        CONTENT_TYPE,
+                        HeaderValue::from_static("application/x-www-form-urlencoded"),
+                    )
+                    .body(hyper::body::Body::from(body))
+                    .map_err(|err| Box::new(err) as Box<dyn error::Error + Send + Sync>)?;
+                let res = request_token(&self.http, req).await?;
+                self.cache.put(Vec::new(), &res);
+                Ok(Some(res.access_token))
+            })
+        }
+    }
+}
+
+/// Utilities to test code using a hub without network access or credentials.
+///
+/// A `MockTransport` answers requests with canned responses, a `Recorder` records the exchanges of a real
+/// transport into a `Cassette`, which a `Replayer` later plays back, and `FakeToken` stands in for an
+/// authenticator.
+pub mod testing {
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+    use hyper::StatusCode;
+    use serde_json as json;
+
+    use super::{redact_url, GetToken, GetTokenFuture, MethodInfo, Transport, TransportFuture};
+
+    /// The id of the method which made the request, if it was made by a call builder.
+    fn method_id(req: &hyper::Request<hyper::body::Body>) -> Option<String> {
+        req.extensions()
+            .get::<MethodInfo>()
+            .map(|info| info.id.to_string())
+    }
+
+    /// A response to hand out instead of asking a server.
+    #[derive(Clone, Debug)]
+    pub struct MockResponse {
+        pub status: StatusCode,
+        pub headers: HeaderMap,
+        pub body: Vec<u8>,
+    }
+
+    impl MockResponse {
+        /// An empty response with the given status.
+        pub fn new(status: StatusCode) -> MockResponse {
+            MockResponse {
+                status,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+            }
+        }
+
+        /// A successful response with the given value as json body.
+        pub fn json<T: ::serde::Serialize>(value: &T) -> MockResponse {
+            MockResponse::new(StatusCode::OK)
+                .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                .body(json::to_vec(value).expect("serde to work"))
+        }
+
+        /// A failed response with the given status, described by an error body as sent by Google APIs.
+        pub fn error(status: StatusCode, message: &str) -> MockResponse {
+            let body = json::json!({
+                "error": {
+                    "code": status.as_u16(),
+                    "message": message,
+                    "errors": [],
+                }
+            });
+            MockResponse {
+                status,
+                ..MockResponse::json(&body)
+            }
+        }
+
+        /// Set the given header, replacing any former value.
+        pub fn header(mut self, name: HeaderName, value: HeaderValue) -> MockResponse {
+            self.headers.insert(name, value);
+            self
+        }
+
+        /// Set the body of the response.
+        pub fn body<T: Into<Vec<u8>>>(mut self, body: T) -> MockResponse {
+            self.body = body.into();
+            self
+        }
+
+        fn to_response(&self) -> hyper::Response<hyper::body::Body> {
+            let mut res = hyper::Response::new(hyper::body::Body::from(self.body.clone()));
+            *res.status_mut() = self.status;
+            *res.headers_mut() = self.headers.clone();
+            res
+        }
+    }
+
+    /// A request received by a `MockTransport`.
+    #[derive(Clone, Debug)]
+    pub struct MockRequest {
+        /// The id of the method which made the request, like `drive.files.get`.
+        pub method_id: Option<String>,
+        pub method: hyper::Method,
+        pub url: String,
+        pub headers: HeaderMap,
+        pub body: Vec<u8>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        responses: Vec<(String, Option<String>, VecDeque<MockResponse>)>,
+        requests: Vec<MockRequest>,
+    }
+
+    /// A transport answering the requests of a hub with canned responses, registered by method id and URL.
+    ///
+    /// Clones share their responses and received requests, so a clone can be handed to the hub while the
+    /// original one is used to inspect the requests afterwards.
+    #[derive(Clone, Default)]
+    pub struct MockTransport {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    impl MockTransport {
+        pub fn new() -> MockTransport {
+            Default::default()
+        }
+
+        /// Answer calls of the method with the given id, like `drive.files.get`, with `response`.
+        ///
+        /// Responses registered for the same method are handed out in order, the last one repeatedly.
+        pub fn respond(&self, method_id: &str, response: MockResponse) -> &MockTransport {
+            self.register(method_id, None, response)
+        }
+
+        /// Like `respond()`, but only for requests to the given URL. It matches if it is the URL of the
+        /// request, either with or without its query.
+        pub fn respond_to(&self, method_id: &str, url: &str, response: MockResponse) -> &MockTransport {
+            self.register(method_id, Some(url.to_string()), response)
+        }
+
+        /// The requests received so far, in order.
+        pub fn requests(&self) -> Vec<MockRequest> {
+            self.state.lock().unwrap().requests.clone()
+        }
+
+        fn register(&self, method_id: &str, url: Option<String>, response: MockResponse) -> &MockTransport {
+            let mut state = self.state.lock().unwrap();
+            match state
+                .responses
+                .iter_mut()
+                .find(|(id, u, _)| id == method_id && *u == url)
+            {
+                Some((_, _, responses)) => responses.push_back(response),
+                None => state
+                    .responses
+                    .push((method_id.to_string(), url, vec![response].into())),
+            }
+            self
+        }
+
+        fn response(&self, request: MockRequest) -> Option<MockResponse> {
+            let mut state = self.state.lock().unwrap();
+            let without_query = request.url.split('?').next().unwrap_or("").to_string();
+            // responses registered for the URL take precedence over the ones for any URL
+            let response = state
+                .responses
+                .iter_mut()
+                .filter(|(id, url, _)| {
+                    request.method_id.as_ref() == Some(id)
+                        && url.as_ref().map_or(true, |url| *url == request.url || *url == without_query)
+                })
+                .max_by_key(|(_, url, _)| url.is_some())
+                .map(|(_, _, responses)| match responses.len() {
+                    1 => responses[0].clone(),
+                    _ => responses.pop_front().unwrap(),
+                });
+            state.requests.push(request);
+            response
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send(&self, req: hyper::Request<hyper::body::Body>) -> TransportFuture {
+            let mock = self.clone();
+            Box::pin(async move {
+                let method_id = method_id(&req);
+                let (parts, body) = req.into_parts();
+                let request = MockRequest {
+                    method_id,
+                    method: parts.method,
+                    url: parts.uri.to_string(),
+                    headers: parts.headers,
+                    body: hyper::body::to_bytes(body).await?.to_vec(),
+                };
+                let description = format!(
+                    "{} {} ({})",
+                    request.method,
+                    redact_url(&request.url),
+                    request.method_id.as_deref().unwrap_or("unknown method")
+                );
+                match mock.response(request) {
+                    Some(response) => Ok(response.to_response()),
+                    None => Err(format!("no response registered for {}", description).into()),
+                }
+            })
+        }
+    }
+
+    /// A request and the response to it, as recorded by a `Recorder`.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Interaction {
+        /// The id of the method which made the request, like `drive.files.get`.
+        #[serde(default)]
+        pub method_id: Option<String>,
+        pub method: String,
+        /// The URL of the request, with API keys and tokens redacted.
+        pub url: String,
+        pub status: u16,
+        pub headers: Vec<(String, String)>,
+        /// The response body, base64 encoded unless it is valid UTF-8.
+        pub body: String,
+        #[serde(default)]
+        pub body_base64: bool,
+    }
+
+    impl Interaction {
+        fn matches(&self, method_id: &Option<String>, method: &str, url: &str) -> bool {
+            self.method_id == *method_id && self.method == method && self.url == url
+        }
+
+        fn to_response(&self) -> std::result::Result<hyper::Response<hyper::body::Body>, super::TransportError> {
+            let body = match self.body_base64 {
+                true => base64::decode(&self.body)?,
+                false => self.body.clone().into_bytes(),
+            };
+            let mut builder = hyper::Response::builder().status(self.status);
+            for (name, value) in self.headers.iter() {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+            Ok(builder.body(hyper::body::Body::from(body))?)
+        }
+    }
+
+    /// Recorded interactions, which can be stored in and loaded from a json file.
+    #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+    pub struct Cassette {
+        pub interactions: Vec<Interaction>,
+    }
+
+    impl Cassette {
+        pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Cassette> {
+            let data = fs::read(path)?;
+            json::from_slice(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+
+        pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+            let data = json::to_vec_pretty(self).expect("serde to work");
+            fs::write(path, data)
+        }
+    }
+
+    /// A transport recording all exchanges made through the transport it wraps.
+    ///
+    /// Response bodies are read into memory as a whole. Request headers and bodies aren't recorded, and API
+    /// keys and tokens are redacted from the recorded URLs.
+    #[derive(Clone)]
+    pub struct Recorder<S> {
+        inner: S,
+        cassette: Arc<Mutex<Cassette>>,
+    }
+
+    impl<S: Transport> Recorder<S> {
+        pub fn new(inner: S) -> Recorder<S> {
+            Recorder {
+                inner,
+                cassette: Default::default(),
+            }
+        }
+
+        /// The interactions recorded so far.
+        pub fn cassette(&self) -> Cassette {
+            self.cassette.lock().unwrap().clone()
+        }
+
+        /// Store the interactions recorded so far in the file at `path`, to replay them using a `Replayer`.
+        pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+            self.cassette().save(path)
+        }
+    }
+
+    impl<S: Transport> Transport for Recorder<S> {
+        fn send(&self, req: hyper::Request<hyper::body::Body>) -> TransportFuture {
+            let method_id = method_id(&req);
+            let method = req.method().to_string();
+            let url = redact_url(&req.uri().to_string());
+            let response = self.inner.send(req);
+            let cassette = self.cassette.clone();
+            Box::pin(async move {
+                let (parts, body) = response.await?.into_parts();
+                let body = hyper::body::to_bytes(body).await?;
+                let (encoded, body_base64) = match std::str::from_utf8(&body) {
+                    Ok(text) => (text.to_string(), false),
+                    Err(_) => (base64::encode(&body), true),
+                };
+                cassette.lock().unwrap().interactions.push(Interaction {
+                    method_id,
+                    method,
+                    url,
+                    status: parts.status.as_u16(),
+                    headers: parts
+                        .headers
+                        .iter()
+                        .map(|(name, value)| {
+                            (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned())
+                        })
+                        .collect(),
+                    body: encoded,
+                    body_base64,
+                });
+                Ok(hyper::Response::from_parts(parts, hyper::body::Body::from(body)))
+            })
+        }
+    }
+
+    /// A transport answering requests with the interactions of a `Cassette`.
+    ///
+    /// Each request is answered by the first interaction not replayed yet which has the same method id,
+    /// HTTP method and URL, with API keys and tokens redacted. Requests without such an interaction fail.
+    #[derive(Clone)]
+    pub struct Replayer {
+        state: Arc<Mutex<(Cassette, Vec<bool>)>>,
+    }
+
+    impl Replayer {
+        pub fn new(cassette: Cassette) -> Replayer {
+            let replayed = vec![false; cassette.interactions.len()];
+            Replayer {
+                state: Arc::new(Mutex::new((cassette, replayed))),
+            }
+        }
+
+        /// Replay the cassette stored in the file at `path`.
+        pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Replayer> {
+            Cassette::load(path).map(Replayer::new)
+        }
+
+        /// Returns true if all interactions of the cassette were replayed.
+        pub fn is_exhausted(&self) -> bool {
+            self.state.lock().unwrap().1.iter().all(|&replayed| replayed)
+        }
+    }
+
+    impl Transport for Replayer {
+        fn send(&self, req: hyper::Request<hyper::body::Body>) -> TransportFuture {
+            let method_id = method_id(&req);
+            let method = req.method().to_string();
+            let url = redact_url(&req.uri().to_string());
+            let mut state = self.state.lock().unwrap();
+            let (ref cassette, ref mut replayed) = *state;
+            let found = cassette
+                .interactions
+                .iter()
+                .enumerate()
+                .find(|(i, interaction)| !replayed[*i] && interaction.matches(&method_id, &method, &url));
+            let result = match found {
+                Some((i, interaction)) => {
+                    replayed[i] = true;
+                    interaction.to_response()
+                }
+                None => Err(format!("no recorded interaction left for {} {}", method, url).into()),
+            };
+            Box::pin(futures::future::ready(result))
+        }
+    }
+
+    /// A token provider handing out a fixed access token, and remembering the scopes it was asked for.
+    ///
+    /// Clones share the requested scopes.
+    #[derive(Clone, Debug)]
+    pub struct FakeToken {
+        token: String,
+        requested_scopes: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl FakeToken {
+        pub fn new<T: Into<String>>(token: T) -> FakeToken {
+            FakeToken {
+                token: token.into(),
+                requested_scopes: Default::default(),
+            }
+        }
+
+        /// The scopes of each token request made so far, in order.
+        pub fn requested_scopes(&self) -> Vec<Vec<String>> {
+            self.requested_scopes.lock().unwrap().clone()
+        }
+    }
+
+    impl Default for FakeToken {
+        fn default() -> FakeToken {
+            FakeToken::new("fake-token")
+        }
+    }
+
+    impl GetToken for FakeToken {
+        fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenFuture<'a> {
+            self.requested_scopes
+                .lock()
+                .unwrap()
+                .push(scopes.iter().map(|s| s.to_string()).collect());
+            Box::pin(futures::future::ready(Ok(Some(self.token.clone()))))
+        }
+    }
+}